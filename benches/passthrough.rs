@@ -0,0 +1,42 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Compares reading through `PartialRead::passthrough` with reading from the
+//! inner reader directly.
+
+use std::io::{self, Read};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use partial_io::PartialRead;
+
+const DATA_SIZE: usize = 1024 * 1024;
+const BUF_SIZE: usize = 64;
+
+fn read_all<R: Read>(mut reader: R) -> usize {
+    let mut buf = [0; BUF_SIZE];
+    let mut total = 0;
+    loop {
+        match reader.read(&mut buf).unwrap() {
+            0 => return total,
+            n => total += black_box(n),
+        }
+    }
+}
+
+fn bench_passthrough(c: &mut Criterion) {
+    let data = vec![0u8; DATA_SIZE];
+    let mut group = c.benchmark_group("read");
+    group.bench_function("bare", |b| b.iter(|| read_all(io::Cursor::new(&data[..]))));
+    group.bench_function("passthrough", |b| {
+        b.iter(|| read_all(PartialRead::passthrough(io::Cursor::new(&data[..]))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_passthrough);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! An example of wrapping the locked standard streams, to manually check how
+//! a line parser copes with short reads and writes from a slow terminal.
+//!
+//! Run it with `cargo run --example stdio`, then type some lines. Every read
+//! from stdin returns at most 3 bytes, and every write to stdout at most 2.
+
+#![deny(warnings)]
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
+
+use partial_io::{PartialOp, PartialRead, PartialWrite};
+
+fn main() -> io::Result<()> {
+    // The wrappers don't require the inner streams to be 'static, so they can
+    // hold locks that borrow from the handles below. Only the op iterators
+    // need to be 'static.
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let reader = PartialRead::new(stdin.lock(), iter::repeat(PartialOp::Limited(3)));
+    let mut writer = PartialWrite::new(stdout.lock(), iter::repeat(PartialOp::Limited(2)));
+
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        writeln!(writer, "{}: {}", number + 1, line?)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
@@ -11,14 +11,25 @@
 //! This is separate from `PartialWrite` because on `WouldBlock` errors, it
 //! causes `futures` to try writing or flushing again.
 
+use std::any;
 use std::cmp;
 use std::fmt;
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, Write};
+use std::pin::Pin;
+use std::time::Instant;
 
-use futures::{task, Poll};
+use futures::{task, Poll, Stream};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use crate::{make_ops, PartialOp};
+use crate::observer::OpObserver;
+use crate::ops::{
+    buffer_too_large, first_cap, injected_error, EmptyOps, OpSource, Operation, Ops, PartialIoStats,
+};
+use crate::read::{
+    carry_limit, check_deadline, read_inner, read_until_delimiter, EofSource, ReadState,
+};
+use crate::readiness::Readiness;
+use crate::{PartialOp, PartialRead};
 
 /// A wrapper that breaks inner `AsyncRead` instances up according to the
 /// provided iterator.
@@ -55,7 +66,13 @@ use crate::{make_ops, PartialOp};
 /// ```
 pub struct PartialAsyncRead<R> {
     inner: R,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops,
+    on_would_block: Option<Box<dyn FnMut() + Send>>,
+    readiness: Option<Readiness>,
+    strict_limit: bool,
+    carried_limit: Option<usize>,
+    last_eof: Option<EofSource>,
+    deadline: Option<Instant>,
 }
 
 impl<R> PartialAsyncRead<R>
@@ -65,39 +82,439 @@ where
     /// Creates a new `PartialAsyncRead` wrapper over the reader with the specified `PartialOp`s.
     pub fn new<I>(inner: R, iter: I) -> Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
+        Self::from_ops(inner, Ops::new(iter))
+    }
+
+    /// Creates a new `PartialAsyncRead` wrapper like `new`, but fails if `iter` is
+    /// empty.
+    ///
+    /// See [`PartialRead::new_nonempty`](struct.PartialRead.html#method.new_nonempty)
+    /// for more.
+    pub fn new_nonempty<I>(inner: R, iter: I) -> Result<Self, EmptyOps>
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Ok(Self::from_ops(inner, Ops::new_nonempty(iter)?))
+    }
+
+    /// Creates a new `PartialAsyncRead` wrapper that replays a trace of read
+    /// results.
+    ///
+    /// Each result is turned into an op with
+    /// [`PartialOp::from_result`](enum.PartialOp.html#method.from_result):
+    /// `Ok(0)` becomes an injected end of file, other successes become limits
+    /// and errors are injected with the same kind, so a `WouldBlock` makes the read return `NotReady`.
+    pub fn from_results<I>(inner: R, results: I) -> Self
+    where
+        I: IntoIterator<Item = io::Result<usize>>,
+    {
+        let ops: Vec<_> = results
+            .into_iter()
+            .map(|res| PartialOp::from_result(&res))
+            .collect();
+        Self::new(inner, ops)
+    }
+
+    fn from_ops(inner: R, ops: Ops) -> Self {
         PartialAsyncRead {
             inner,
-            ops: make_ops(iter),
+            ops,
+            on_would_block: None,
+            readiness: None,
+            strict_limit: false,
+            carried_limit: None,
+            last_eof: None,
+            deadline: None,
         }
     }
 
+    /// Creates a new `PartialAsyncRead` wrapper whose `WouldBlock` ops stay
+    /// blocked until they are released through `releases`.
+    ///
+    /// Rather than notifying the current task right away, a `WouldBlock` op
+    /// registers the task with `releases`, typically the receiving end of a
+    /// `futures::sync::mpsc` channel. Every read returns `WouldBlock` (and
+    /// therefore `NotReady`) without consuming any more ops until a `()` is
+    /// received, at which point the op is complete and reads go on with the
+    /// next op. This lets a test decide exactly when each blocked read becomes
+    /// ready.
+    ///
+    /// A `()` sent before the `WouldBlock` op is applied releases it right
+    /// away. If `releases` ends or fails, nothing is blocked any more. A
+    /// closure set through `set_on_would_block` is not called in this mode.
+    ///
+    /// Reads must happen within a task, since that is what gets notified.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor};
+    ///
+    /// use futures::sync::mpsc;
+    /// use futures::{executor, Future};
+    /// use tokio_io::io::read as tokio_read;
+    ///
+    /// use partial_io::{PartialAsyncRead, PartialOp};
+    ///
+    /// let (release, releases) = mpsc::unbounded();
+    /// let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(2)];
+    /// let partial_reader = PartialAsyncRead::with_releases(Cursor::new(vec![1, 2, 3]), ops, releases);
+    ///
+    /// release.unbounded_send(()).unwrap();
+    /// let (_, out, size) = executor::spawn(tokio_read(partial_reader, vec![0; 4]))
+    ///     .wait_future()
+    ///     .unwrap();
+    /// assert_eq!(&out[..size], &[1, 2]);
+    /// ```
+    pub fn with_releases<I, S>(inner: R, iter: I, releases: S) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+        S: Stream<Item = (), Error = ()> + Send + 'static,
+    {
+        let mut reader = Self::new(inner, iter);
+        reader.readiness = Some(Readiness::new(releases));
+        reader
+    }
+
     /// Sets the `PartialOp`s for this reader.
     pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.ops.set(iter);
+        self.carried_limit = None;
+        self
+    }
+
+    /// Sets the `PartialOp`s for this reader, and starts counting consumed ops
+    /// over from zero.
+    ///
+    /// See [`PartialRead::reset_ops`](struct.PartialRead.html#method.reset_ops)
+    /// for more.
+    pub fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.set_ops(iter);
+        self.ops.reset_counts();
+        self
+    }
+
+    /// Sets whether `PartialOp::Limited` caps are enforced strictly.
+    ///
+    /// See [`PartialRead::set_strict_limit`](struct.PartialRead.html#method.set_strict_limit)
+    /// for more.
+    pub fn set_strict_limit(&mut self, strict: bool) -> &mut Self {
+        self.strict_limit = strict;
+        if !strict {
+            self.carried_limit = None;
+        }
         self
     }
 
+    /// Adds an observer to notify about every op this reader applies.
+    ///
+    /// Any number of observers can be added, and they coexist with recording.
+    /// See [`OpObserver`] for more.
+    ///
+    /// [`OpObserver`]: trait.OpObserver.html
+    pub fn add_observer(&mut self, observer: impl OpObserver + 'static) -> &mut Self {
+        self.ops.add_observer(Box::new(observer));
+        self
+    }
+
+    /// Skips up to `n` ops without performing any IO, returning how many were
+    /// actually skipped.
+    ///
+    /// See [`PartialRead::skip_ops`](struct.PartialRead.html#method.skip_ops)
+    /// for more.
+    pub fn skip_ops(&mut self, n: usize) -> usize {
+        self.ops.skip_ops(n)
+    }
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    pub fn ops_consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns counts of the `PartialOp`s applied so far, by kind.
+    ///
+    /// See [`PartialIoStats`](struct.PartialIoStats.html) for more.
+    pub fn stats(&self) -> PartialIoStats {
+        self.ops.stats()
+    }
+
+    /// Panics when this reader is dropped if any of its `PartialOp`s were
+    /// never consumed.
+    ///
+    /// See [`PartialRead::expect_all_consumed`](struct.PartialRead.html#method.expect_all_consumed)
+    /// for more.
+    pub fn expect_all_consumed(&mut self) -> &mut Self {
+        self.ops.expect_all_consumed();
+        self
+    }
+
+    /// Caps the number of `PartialOp::Err(WouldBlock)` ops applied in a row.
+    ///
+    /// Once `max` `WouldBlock` ops in a row have made reads return `NotReady`,
+    /// the rest of the run of `WouldBlock` ops is skipped, and the next read
+    /// applies the first op after it. The skipped ops count towards
+    /// `ops_consumed`, but aren't recorded. This is a safety valve against
+    /// generated scripts that starve a test of progress; when it kicks in, the
+    /// script no longer runs as written, so tests that care about the exact
+    /// schedule shouldn't rely on it. Pass `None` to remove the cap.
+    ///
+    /// At most 1024 ops are skipped at a time. If the run is longer than that,
+    /// e.g. because the script repeats `WouldBlock` forever, the read fails
+    /// with an `ErrorKind::Other` error instead of hanging.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor};
+    ///
+    /// use futures::Async;
+    /// use partial_io::{PartialAsyncRead, PartialOp};
+    /// use tokio_io::AsyncRead;
+    ///
+    /// let ops = vec![
+    ///     PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///     PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///     PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///     PartialOp::Limited(2),
+    /// ];
+    /// let mut reader = PartialAsyncRead::new(Cursor::new(vec![1, 2, 3]), ops);
+    /// reader.set_max_consecutive_blocks(Some(1));
+    /// // Don't notify a task, since there isn't one.
+    /// reader.set_on_would_block(Box::new(|| {}));
+    ///
+    /// let mut out = vec![0; 8];
+    /// assert_eq!(reader.poll_read(&mut out).unwrap(), Async::NotReady);
+    /// // The other two blocks are skipped.
+    /// assert_eq!(reader.poll_read(&mut out).unwrap(), Async::Ready(2));
+    /// assert_eq!(reader.ops_consumed(), 4);
+    /// ```
+    pub fn set_max_consecutive_blocks(&mut self, max: Option<usize>) -> &mut Self {
+        self.ops.set_max_consecutive_blocks(max);
+        self
+    }
+
+    /// Sets whether a fatal injected error poisons this reader.
+    ///
+    /// Once a `PartialOp::Err` op with a kind other than `WouldBlock` or
+    /// `Interrupted` has fired, every later read fails with
+    /// `ErrorKind::NotConnected` without consuming an op.
+    ///
+    /// See [`PartialRead::set_poison_on_error`](struct.PartialRead.html#method.set_poison_on_error)
+    /// for more.
+    pub fn set_poison_on_error(&mut self, poison: bool) -> &mut Self {
+        self.ops.set_poison_on_error(poison);
+        self
+    }
+
+    /// Sets whether to record the `PartialOp`s applied by this reader.
+    ///
+    /// See [`PartialRead::set_recording`](struct.PartialRead.html#method.set_recording)
+    /// for more.
+    pub fn set_recording(&mut self, record: bool) -> &mut Self {
+        self.ops.set_recording(record);
+        self
+    }
+
+    /// Returns the ops recorded so far, along with the number of bytes each
+    /// transferred.
+    pub fn recorded(&self) -> &[(PartialOp, usize)] {
+        self.ops.recorded()
+    }
+
+    /// Removes the ops recorded so far from the log, yielding them in order.
+    ///
+    /// See [`PartialRead::drain_events`](struct.PartialRead.html#method.drain_events)
+    /// for more.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = (PartialOp, usize)> + '_ {
+        self.ops.drain_recorded()
+    }
+
+    /// Writes the recorded ops to `w`, one per line.
+    ///
+    /// See [`PartialRead::dump_recorded`](struct.PartialRead.html#method.dump_recorded)
+    /// for the format.
+    pub fn dump_recorded(&self, w: &mut impl Write) -> io::Result<()> {
+        self.ops.dump_recorded(w)
+    }
+
+    /// Sets a closure to run whenever a `WouldBlock` op is applied.
+    ///
+    /// By default, a `WouldBlock` op notifies the current task so that it gets
+    /// polled again. If a closure is set, it is called instead, which allows a
+    /// custom executor to be prodded explicitly. The closure runs after the
+    /// op is consumed and before the `WouldBlock` error (and therefore
+    /// `NotReady`) is returned.
+    pub fn set_on_would_block(&mut self, f: Box<dyn FnMut() + Send>) -> &mut Self {
+        self.on_would_block = Some(f);
+        self
+    }
+
+    /// Sets a point in time after which every read returns `NotReady`.
+    ///
+    /// Unlike a `WouldBlock` op, this doesn't notify the current task, since a
+    /// stalled connection doesn't become ready again on its own. See
+    /// [`PartialRead::set_deadline`](struct.PartialRead.html#method.set_deadline)
+    /// for more.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Returns whether the most recent read returning `Ok(0)` did so because
+    /// of a `PartialOp::Eof` op, rather than the inner reader.
+    ///
+    /// See [`PartialRead::last_eof_was_injected`](struct.PartialRead.html#method.last_eof_was_injected)
+    /// for more.
+    pub fn last_eof_was_injected(&self) -> bool {
+        self.last_eof == Some(EofSource::Injected)
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.inner
     }
 
     /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// If the reader is pinned and `R` is not `Unpin`, use `get_pin_mut` instead.
     pub fn get_mut(&mut self) -> &mut R {
         &mut self.inner
     }
 
+    /// Acquires a pinned reference to the underlying reader.
+    ///
+    /// This works even if `R` is not `Unpin`.
+    pub fn get_pin_ref(self: Pin<&Self>) -> Pin<&R> {
+        // This is safe because `inner` is structurally pinned: it is never
+        // moved out of a pinned wrapper, and the wrapper is only `Unpin` if
+        // `R` is.
+        unsafe { self.map_unchecked(|this| &this.inner) }
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// This works even if `R` is not `Unpin`, unlike `get_mut`.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        // See `get_pin_ref` for why this is safe.
+        unsafe { self.map_unchecked_mut(|this| &mut this.inner) }
+    }
+
     /// Consumes this wrapper, returning the underlying reader.
+    ///
+    /// A pinned wrapper can only be consumed this way if `R` is `Unpin`.
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    /// Converts this wrapper into a `PartialRead`, keeping the remaining ops.
+    ///
+    /// Everything that applies to the ops carries over: the number of ops
+    /// consumed so far, recording, observers, `expect_all_consumed`, a strictly
+    /// enforced limit in progress and the deadline. The `WouldBlock` callback
+    /// and releases set up with `with_releases` only make sense for async
+    /// readers, so they are dropped.
+    pub fn into_sync(self) -> PartialRead<R> {
+        PartialRead::from_state(ReadState {
+            inner: self.inner,
+            ops: self.ops,
+            strict_limit: self.strict_limit,
+            carried_limit: self.carried_limit,
+            last_eof: self.last_eof,
+            deadline: self.deadline,
+        })
+    }
+
+    fn notify_would_block(&mut self) {
+        match (&mut self.readiness, &mut self.on_would_block) {
+            (Some(readiness), _) => {
+                readiness.block();
+                // Polling registers this task to be notified on release. If a
+                // release was already queued, make sure this task is rechecked.
+                if !readiness.poll_blocked() {
+                    task::park().unpark();
+                }
+            }
+            (None, Some(f)) => f(),
+            // Make sure this task is rechecked.
+            (None, None) => task::park().unpark(),
+        }
+    }
+}
+
+impl<R> PartialRead<R>
+where
+    R: AsyncRead,
+{
+    /// Converts this wrapper into a `PartialAsyncRead`, keeping the remaining
+    /// ops.
+    ///
+    /// Available with the `tokio` feature.
+    ///
+    /// This allows a reader to be set up, and possibly partly consumed, in
+    /// synchronous code before handing it over to async code. Everything that
+    /// applies to the ops carries over: the number of ops consumed so far,
+    /// recording, observers, `expect_all_consumed`, a strictly enforced limit
+    /// in progress and the deadline. The post-seek op and
+    /// `set_limit_only_above` have no async counterpart, so they are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = vec![PartialOp::Limited(1), PartialOp::Limited(2)];
+    /// let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+    /// let mut out = vec![0; 8];
+    /// assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    ///
+    /// let mut partial_async_read = partial_read.into_async();
+    /// assert_eq!(partial_async_read.ops_consumed(), 1);
+    /// assert_eq!(partial_async_read.read(&mut out).unwrap(), 2);
+    /// ```
+    pub fn into_async(self) -> PartialAsyncRead<R> {
+        let state = self.into_state();
+        let mut reader = PartialAsyncRead::from_ops(state.inner, state.ops);
+        reader.strict_limit = state.strict_limit;
+        reader.carried_limit = state.carried_limit;
+        reader.last_eof = state.last_eof;
+        reader.deadline = state.deadline;
+        reader
+    }
+}
+
+impl<R> PartialAsyncRead<R>
+where
+    R: Seek,
+{
+    /// Returns the current position of the inner reader, without consuming an
+    /// op.
+    ///
+    /// See [`PartialRead::position`](struct.PartialRead.html#method.position)
+    /// for more.
+    pub fn position(&mut self) -> io::Result<u64> {
+        self.inner.stream_position()
+    }
 }
 
 impl<R> Read for PartialAsyncRead<R>
@@ -105,27 +522,85 @@ where
     R: AsyncRead,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.ops.next() {
+        self.ops.check_fail_flag(Operation::Read)?;
+        check_deadline(self.deadline)?;
+        self.ops.check_poisoned(Operation::Read)?;
+        self.ops.skip_blocks(Operation::Read)?;
+        if self
+            .readiness
+            .as_mut()
+            .map_or(false, Readiness::poll_blocked)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "read not released yet, generated by partial-io",
+            ));
+        }
+        if self.carried_limit.is_none() && self.ops.fits_buffer_limit(buf.len()) {
+            let res = read_inner(&mut self.inner, buf);
+            if let Some(source) = EofSource::of(None, &res) {
+                self.last_eof = Some(source);
+            }
+            return res;
+        }
+        let (op, source) = match self.carried_limit.take() {
+            Some(n) => (Some(PartialOp::Limited(n)), OpSource::Carried),
+            None => (self.ops.next(), OpSource::Script),
+        };
+        let res = match op {
             Some(PartialOp::Limited(n)) => {
                 let len = cmp::min(n, buf.len());
-                self.inner.read(&mut buf[..len])
+                let res = read_inner(&mut self.inner, &mut buf[..len]);
+                if self.strict_limit {
+                    self.carried_limit = carry_limit(n, &res);
+                }
+                res
+            }
+            Some(PartialOp::LimitedThenErr(n, _)) | Some(PartialOp::DropTail(n)) => {
+                let len = cmp::min(n, buf.len());
+                read_inner(&mut self.inner, &mut buf[..len])
             }
             Some(PartialOp::Err(err)) => {
                 if err == io::ErrorKind::WouldBlock {
-                    // Make sure this task is rechecked.
-                    task::park().unpark();
+                    self.notify_would_block();
                 }
-                Err(io::Error::new(
-                    err,
-                    "error during read, generated by partial-io",
-                ))
+                Err(injected_error(err, Operation::Read))
+            }
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let len = first_cap(caps, buf.len());
+                read_inner(&mut self.inner, &mut buf[..len])
+            }
+            Some(PartialOp::BufferTooLarge(max)) => Err(buffer_too_large(max)),
+            Some(PartialOp::Delimiter(delimiter)) => {
+                read_until_delimiter(&mut self.inner, buf, delimiter)
             }
-            Some(PartialOp::Unlimited) | None => self.inner.read(buf),
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => {
+                read_inner(&mut self.inner, buf)
+            }
+        };
+        if let Some(eof_source) = EofSource::of(op.as_ref(), &res) {
+            self.last_eof = Some(eof_source);
         }
+        self.ops
+            .complete_from(source, op, res.as_ref().ok().copied());
+        res
     }
 }
 
-impl<R> AsyncRead for PartialAsyncRead<R> where R: AsyncRead {}
+impl<R> AsyncRead for PartialAsyncRead<R>
+where
+    R: AsyncRead,
+{
+    // `read` only ever passes a prefix of the buffer to the inner reader, so
+    // whatever the inner reader needs initialized is enough here as well. This
+    // also means that `read_buf` only advances a `BufMut` by the number of
+    // bytes actually read, so partial reads into the same buffer across several
+    // polls accumulate in order.
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
 
 // Forwarding impls to support duplex structs.
 impl<R> Write for PartialAsyncRead<R>
@@ -153,13 +628,11 @@ where
     }
 }
 
-impl<R> fmt::Debug for PartialAsyncRead<R>
-where
-    R: fmt::Debug,
-{
+impl<R> fmt::Debug for PartialAsyncRead<R> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PartialAsyncRead")
-            .field("inner", &self.inner)
+            .field("inner", &format_args!("{}", any::type_name::<R>()))
+            .field("ops_consumed", &self.ops.consumed())
             .finish()
     }
 }
@@ -169,11 +642,295 @@ mod tests {
     use super::*;
 
     use std::fs::File;
+    use std::io::Cursor;
+    use std::iter;
+    use std::marker::PhantomPinned;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use bytes::BytesMut;
+    use futures::executor::{self, Notify, NotifyHandle};
+    use futures::sync::mpsc;
+    use futures::Async;
+    use tokio_core::reactor::Core;
+    use tokio_io::io::read as tokio_read;
 
     use crate::tests::assert_send;
 
+    /// Counts how many times a task was notified.
+    #[derive(Default)]
+    struct CountNotify(AtomicUsize);
+
+    impl Notify for CountNotify {
+        fn notify(&self, _id: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// A reader that cannot be moved once pinned.
+    struct PinnedReader {
+        inner: Cursor<Vec<u8>>,
+        pins: usize,
+        _pin: PhantomPinned,
+    }
+
+    impl PinnedReader {
+        fn bump(self: Pin<&mut Self>) {
+            // Updating a plain field doesn't move the reader.
+            unsafe { self.get_unchecked_mut().pins += 1 };
+        }
+    }
+
+    impl Read for PinnedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl AsyncRead for PinnedReader {}
+
     #[test]
     fn test_sendable() {
         assert_send::<PartialAsyncRead<File>>();
     }
+
+    #[test]
+    fn test_pinned_accessors() {
+        let reader = PinnedReader {
+            inner: Cursor::new(vec![1, 2, 3]),
+            pins: 0,
+            _pin: PhantomPinned,
+        };
+        let mut partial_reader = Box::pin(PartialAsyncRead::new(reader, Vec::<PartialOp>::new()));
+
+        assert_eq!(partial_reader.as_ref().get_pin_ref().inner.position(), 0);
+        partial_reader.as_mut().get_pin_mut().bump();
+        partial_reader.as_mut().get_pin_mut().bump();
+        assert_eq!(partial_reader.as_ref().get_pin_ref().pins, 2);
+    }
+
+    #[test]
+    fn test_on_would_block() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut partial_reader = PartialAsyncRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+        partial_reader.set_on_would_block(Box::new(move || {
+            calls2.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        // No task is running here, so this would panic if the task were notified.
+        let mut out = vec![0; 256];
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::Ready(2));
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_max_consecutive_blocks() {
+        let block = PartialOp::Err(io::ErrorKind::WouldBlock);
+        let mut ops = vec![block.clone(); 5];
+        ops.extend(vec![
+            PartialOp::Limited(2),
+            block.clone(),
+            PartialOp::Limited(1),
+        ]);
+        let mut partial_reader = PartialAsyncRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+        partial_reader.set_max_consecutive_blocks(Some(3));
+        partial_reader.set_recording(true);
+        partial_reader.set_on_would_block(Box::new(|| {}));
+
+        let mut out = vec![0; 256];
+        for _ in 0..3 {
+            assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+        }
+        // The fourth and fifth blocks are skipped.
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::Ready(2));
+        assert_eq!(partial_reader.ops_consumed(), 6);
+        // Progress resets the count, so the next block still applies.
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::Ready(1));
+        assert_eq!(
+            partial_reader.recorded(),
+            &[
+                (block.clone(), 0),
+                (block.clone(), 0),
+                (block.clone(), 0),
+                (PartialOp::Limited(2), 2),
+                (block, 0),
+                (PartialOp::Limited(1), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_consecutive_blocks_endless() {
+        let ops = iter::repeat(PartialOp::Err(io::ErrorKind::WouldBlock));
+        let mut partial_reader = PartialAsyncRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+        partial_reader.set_max_consecutive_blocks(Some(2));
+        partial_reader.set_on_would_block(Box::new(|| {}));
+
+        let mut out = vec![0; 256];
+        for _ in 0..2 {
+            assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+        }
+        // Skipping can't get past the run, so the read fails instead of hanging.
+        let err = partial_reader.poll_read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(err
+            .to_string()
+            .contains("max_consecutive_blocks exhausted the script"));
+    }
+
+    #[test]
+    fn test_strict_limit_counts_one_op() {
+        let inner = PartialAsyncRead::new(
+            Cursor::new((0..16).collect::<Vec<u8>>()),
+            iter::repeat(PartialOp::Limited(2)),
+        );
+        let mut partial_reader = PartialAsyncRead::new(inner, vec![PartialOp::Limited(10)]);
+        partial_reader.set_strict_limit(true).set_recording(true);
+
+        let mut out = vec![0; 16];
+        for _ in 0..5 {
+            assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::Ready(2));
+        }
+        assert_eq!(partial_reader.ops_consumed(), 1);
+        assert_eq!(partial_reader.stats().limited, 1);
+        assert_eq!(partial_reader.recorded(), &[(PartialOp::Limited(10), 10)]);
+    }
+
+    #[test]
+    fn test_read_buf_across_polls() {
+        let ops = vec![PartialOp::Limited(3), PartialOp::Limited(3)];
+        let mut partial_reader =
+            PartialAsyncRead::new(Cursor::new((0..16).collect::<Vec<u8>>()), ops);
+        let mut buf = BytesMut::with_capacity(10);
+
+        assert_eq!(
+            AsyncRead::read_buf(&mut partial_reader, &mut buf).unwrap(),
+            Async::Ready(3)
+        );
+        assert_eq!(
+            AsyncRead::read_buf(&mut partial_reader, &mut buf).unwrap(),
+            Async::Ready(3)
+        );
+        assert_eq!(buf.len(), 6);
+        assert_eq!(&buf[..], &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_with_releases() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+        ];
+        let (release, releases) = mpsc::unbounded();
+        let partial_reader =
+            PartialAsyncRead::with_releases(Cursor::new(vec![1, 2, 3, 4]), ops, releases);
+        let mut fut = executor::spawn(tokio_read(partial_reader, vec![0; 256]));
+        let notify = Arc::new(CountNotify::default());
+        let handle = NotifyHandle::from(notify.clone());
+
+        // The read stays blocked, without waking itself up, until released.
+        assert!(fut.poll_future_notify(&handle, 0).unwrap().is_not_ready());
+        assert!(fut.poll_future_notify(&handle, 0).unwrap().is_not_ready());
+        assert_eq!(notify.0.load(Ordering::SeqCst), 0);
+
+        release.unbounded_send(()).unwrap();
+        assert_eq!(notify.0.load(Ordering::SeqCst), 1);
+        match fut.poll_future_notify(&handle, 0).unwrap() {
+            Async::Ready((_, out, size)) => assert_eq!(&out[..size], &[1, 2]),
+            Async::NotReady => panic!("read should have been released"),
+        }
+    }
+
+    #[test]
+    fn test_from_results() {
+        let trace = vec![
+            Ok(2),
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "blocked")),
+            Ok(3),
+        ];
+        let mut partial_reader =
+            PartialAsyncRead::from_results(Cursor::new((0..8).collect::<Vec<u8>>()), trace);
+        partial_reader.set_recording(true);
+
+        let mut core = Core::new().unwrap();
+        let (partial_reader, out, size) = core.run(tokio_read(partial_reader, vec![0; 8])).unwrap();
+        assert_eq!(&out[..size], &[0, 1]);
+        // This read blocks once before going through.
+        let (partial_reader, out, size) = core.run(tokio_read(partial_reader, out)).unwrap();
+        assert_eq!(&out[..size], &[2, 3, 4]);
+        assert_eq!(
+            partial_reader.recorded(),
+            &[
+                (PartialOp::Limited(2), 2),
+                (PartialOp::Err(io::ErrorKind::WouldBlock), 0),
+                (PartialOp::Limited(3), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut partial_reader =
+            PartialAsyncRead::new(Cursor::new(vec![1, 2, 3]), Vec::<PartialOp>::new());
+        partial_reader.set_deadline(Some(Instant::now()));
+
+        // No task is running here, so this would panic if the task were notified.
+        let mut out = vec![0; 8];
+        assert_eq!(partial_reader.poll_read(&mut out).unwrap(), Async::NotReady);
+    }
+
+    #[test]
+    fn test_into_async_and_back() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new((0..8).collect::<Vec<u8>>()), ops);
+        partial_read.expect_all_consumed();
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+
+        let partial_reader = partial_read.into_async();
+        assert_eq!(partial_reader.ops_consumed(), 1);
+        let (partial_reader, out, size) = executor::spawn(tokio_read(partial_reader, out))
+            .wait_future()
+            .unwrap();
+        assert_eq!(&out[..size], &[1, 2]);
+        assert_eq!(partial_reader.ops_consumed(), 3);
+
+        let mut partial_read = partial_reader.into_sync();
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(&out[..3], &[3, 4, 5]);
+        assert_eq!(partial_read.ops_consumed(), 4);
+    }
+
+    #[test]
+    fn test_into_async_keeps_fail_flag() {
+        let partial_read = PartialRead::with_fail_flag(
+            Cursor::new(vec![1, 2, 3]),
+            vec![PartialOp::Limited(1)],
+            Arc::new(AtomicBool::new(true)),
+            io::ErrorKind::Other,
+        );
+        let mut partial_reader = partial_read.into_async();
+
+        let mut out = vec![0; 8];
+        let err = partial_reader.poll_read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        // The failure didn't consume the op.
+        assert_eq!(partial_reader.ops_consumed(), 0);
+    }
 }
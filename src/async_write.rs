@@ -11,14 +11,23 @@
 //! This is separate from `PartialWrite` because on `WouldBlock` errors, it
 //! causes `futures` to try writing or flushing again.
 
+use std::any;
 use std::cmp;
 use std::fmt;
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, Write};
+use std::pin::Pin;
 
-use futures::{task, Poll};
+use futures::{task, Async, Poll, Stream};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use crate::{make_ops, PartialOp};
+use crate::observer::OpObserver;
+use crate::ops::{
+    buffer_too_large, delimited_len, first_cap, injected_error, op_limit, EmptyOps, Operation, Ops,
+    PartialIoStats,
+};
+use crate::readiness::Readiness;
+use crate::write::write_drop_tail;
+use crate::{PartialOp, WrittenBytes};
 
 /// A wrapper that breaks inner `AsyncWrite` instances up according to the
 /// provided iterator.
@@ -55,7 +64,18 @@ use crate::{make_ops, PartialOp};
 /// ```
 pub struct PartialAsyncWrite<W> {
     inner: W,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops,
+    on_would_block: Option<Box<dyn FnMut() + Send>>,
+    readiness: Option<Readiness>,
+    enforce_flush_before_shutdown: bool,
+    unflushed: u64,
+    send_buffer: Option<SendBuffer>,
+}
+
+/// Written bytes that haven't been passed on to the inner writer yet.
+struct SendBuffer {
+    data: Vec<u8>,
+    capacity: usize,
 }
 
 impl<W> PartialAsyncWrite<W>
@@ -65,34 +85,424 @@ where
     /// Creates a new `PartialAsyncWrite` wrapper over the writer with the specified `PartialOp`s.
     pub fn new<I>(inner: W, iter: I) -> Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Self::from_ops(inner, Ops::new(iter))
+    }
+
+    /// Creates a new `PartialAsyncWrite` wrapper like `new`, but fails if `iter` is
+    /// empty.
+    ///
+    /// See [`PartialRead::new_nonempty`](struct.PartialRead.html#method.new_nonempty)
+    /// for more.
+    pub fn new_nonempty<I>(inner: W, iter: I) -> Result<Self, EmptyOps>
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
+        Ok(Self::from_ops(inner, Ops::new_nonempty(iter)?))
+    }
+
+    /// Creates a new `PartialAsyncWrite` wrapper that replays a trace of write
+    /// results.
+    ///
+    /// Each result is turned into an op with
+    /// [`PartialOp::from_result`](enum.PartialOp.html#method.from_result):
+    /// `Ok(0)` becomes an injected end of file, other successes become limits
+    /// and errors are injected with the same kind, so a `WouldBlock` makes the write return `NotReady`.
+    pub fn from_results<I>(inner: W, results: I) -> Self
+    where
+        I: IntoIterator<Item = io::Result<usize>>,
+    {
+        let ops: Vec<_> = results
+            .into_iter()
+            .map(|res| PartialOp::from_result(&res))
+            .collect();
+        Self::new(inner, ops)
+    }
+
+    fn from_ops(inner: W, ops: Ops) -> Self {
         PartialAsyncWrite {
             inner,
-            ops: make_ops(iter),
+            ops,
+            on_would_block: None,
+            readiness: None,
+            enforce_flush_before_shutdown: false,
+            unflushed: 0,
+            send_buffer: None,
         }
     }
 
+    /// Creates a new `PartialAsyncWrite` wrapper whose `WouldBlock` ops stay
+    /// blocked until they are released through `releases`.
+    ///
+    /// Both writes and flushes are blocked. See
+    /// [`PartialAsyncRead::with_releases`](struct.PartialAsyncRead.html#method.with_releases)
+    /// for more.
+    pub fn with_releases<I, S>(inner: W, iter: I, releases: S) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+        S: Stream<Item = (), Error = ()> + Send + 'static,
+    {
+        let mut writer = Self::new(inner, iter);
+        writer.readiness = Some(Readiness::new(releases));
+        writer
+    }
+
+    /// Creates a new `PartialAsyncWrite` wrapper that models a send buffer of
+    /// `capacity` bytes.
+    ///
+    /// Writes are accepted into the buffer for as long as there is room, and
+    /// fail with `WouldBlock` (and therefore `NotReady`) once it is full, just
+    /// like a socket whose send buffer has filled up. The ops control how
+    /// quickly the buffer drains to the inner writer instead of limiting
+    /// writes directly: if the buffer isn't empty, every write and flush first
+    /// applies the next op to pass on part of it. For example, `Limited(n)`
+    /// passes on up to `n` bytes and `WouldBlock` stalls the call. A flush
+    /// only reaches the inner writer once the buffer is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn with_buffer_capacity<I>(inner: W, iter: I, capacity: usize) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        assert!(capacity > 0, "send buffer capacity must be nonzero");
+        let mut writer = Self::new(inner, iter);
+        writer.send_buffer = Some(SendBuffer {
+            data: Vec::with_capacity(capacity),
+            capacity,
+        });
+        writer
+    }
+
     /// Sets the `PartialOp`s for this reader.
     pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.ops.set(iter);
+        self
+    }
+
+    /// Sets the `PartialOp`s for this writer, and starts counting consumed ops
+    /// over from zero.
+    ///
+    /// See [`PartialRead::reset_ops`](struct.PartialRead.html#method.reset_ops)
+    /// for more.
+    pub fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.set_ops(iter);
+        self.ops.reset_counts();
+        self
+    }
+
+    /// Adds an observer to notify about every op this writer applies.
+    ///
+    /// Any number of observers can be added, and they coexist with recording.
+    /// See [`OpObserver`] for more.
+    ///
+    /// [`OpObserver`]: trait.OpObserver.html
+    pub fn add_observer(&mut self, observer: impl OpObserver + 'static) -> &mut Self {
+        self.ops.add_observer(Box::new(observer));
+        self
+    }
+
+    /// Skips up to `n` ops without performing any IO, returning how many were
+    /// actually skipped.
+    ///
+    /// See [`PartialRead::skip_ops`](struct.PartialRead.html#method.skip_ops)
+    /// for more.
+    pub fn skip_ops(&mut self, n: usize) -> usize {
+        self.ops.skip_ops(n)
+    }
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    pub fn ops_consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns counts of the `PartialOp`s applied so far, by kind.
+    ///
+    /// See [`PartialIoStats`](struct.PartialIoStats.html) for more.
+    pub fn stats(&self) -> PartialIoStats {
+        self.ops.stats()
+    }
+
+    /// Panics when this writer is dropped if any of its `PartialOp`s were
+    /// never consumed.
+    ///
+    /// See [`PartialRead::expect_all_consumed`](struct.PartialRead.html#method.expect_all_consumed)
+    /// for more.
+    pub fn expect_all_consumed(&mut self) -> &mut Self {
+        self.ops.expect_all_consumed();
+        self
+    }
+
+    /// Caps the number of `PartialOp::Err(WouldBlock)` ops applied in a row.
+    ///
+    /// Once `max` `WouldBlock` ops in a row have made writes return `NotReady`,
+    /// the rest of the run of `WouldBlock` ops is skipped, and the next write
+    /// applies the first op after it. The skipped ops count towards
+    /// `ops_consumed`, but aren't recorded. This is a safety valve against
+    /// generated scripts that starve a test of progress; when it kicks in, the
+    /// script no longer runs as written, so tests that care about the exact
+    /// schedule shouldn't rely on it. Pass `None` to remove the cap.
+    ///
+    /// If the run goes on for more than 1024 ops, the operation fails with an
+    /// `ErrorKind::Other` error instead.
+    ///
+    /// See [`PartialAsyncRead::set_max_consecutive_blocks`](struct.PartialAsyncRead.html#method.set_max_consecutive_blocks)
+    /// for more.
+    pub fn set_max_consecutive_blocks(&mut self, max: Option<usize>) -> &mut Self {
+        self.ops.set_max_consecutive_blocks(max);
+        self
+    }
+
+    /// Sets whether a fatal injected error poisons this writer.
+    ///
+    /// Once a `PartialOp::Err` op with a kind other than `WouldBlock` or
+    /// `Interrupted` has fired, every later write, flush or shutdown fails with
+    /// `ErrorKind::NotConnected` without consuming an op.
+    ///
+    /// See [`PartialRead::set_poison_on_error`](struct.PartialRead.html#method.set_poison_on_error)
+    /// for more.
+    pub fn set_poison_on_error(&mut self, poison: bool) -> &mut Self {
+        self.ops.set_poison_on_error(poison);
+        self
+    }
+
+    /// Sets whether to record the `PartialOp`s applied by this writer.
+    ///
+    /// See [`PartialRead::set_recording`](struct.PartialRead.html#method.set_recording)
+    /// for more.
+    pub fn set_recording(&mut self, record: bool) -> &mut Self {
+        self.ops.set_recording(record);
         self
     }
 
+    /// Returns the ops recorded so far, along with the number of bytes each
+    /// transferred.
+    pub fn recorded(&self) -> &[(PartialOp, usize)] {
+        self.ops.recorded()
+    }
+
+    /// Removes the ops recorded so far from the log, yielding them in order.
+    ///
+    /// See [`PartialRead::drain_events`](struct.PartialRead.html#method.drain_events)
+    /// for more.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = (PartialOp, usize)> + '_ {
+        self.ops.drain_recorded()
+    }
+
+    /// Writes the recorded ops to `w`, one per line.
+    ///
+    /// See [`PartialRead::dump_recorded`](struct.PartialRead.html#method.dump_recorded)
+    /// for the format.
+    pub fn dump_recorded(&self, w: &mut impl Write) -> io::Result<()> {
+        self.ops.dump_recorded(w)
+    }
+
+    /// Sets a closure to run whenever a `WouldBlock` op is applied.
+    ///
+    /// See [`PartialAsyncRead::set_on_would_block`](struct.PartialAsyncRead.html#method.set_on_would_block)
+    /// for more.
+    pub fn set_on_would_block(&mut self, f: Box<dyn FnMut() + Send>) -> &mut Self {
+        self.on_would_block = Some(f);
+        self
+    }
+
+    /// Sets whether to panic if `shutdown` is called before all writes were
+    /// flushed.
+    ///
+    /// The wrapper counts the bytes written since the last successful flush.
+    /// With this enabled, calling `shutdown` while that count is nonzero
+    /// panics, reporting the number of unflushed bytes. This catches code that
+    /// shuts down a connection with data still buffered, e.g. by queueing
+    /// writes without driving `poll_flush` to completion first.
+    pub fn set_enforce_flush_before_shutdown(&mut self, enforce: bool) -> &mut Self {
+        self.enforce_flush_before_shutdown = enforce;
+        self
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
     /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// If the writer is pinned and `W` is not `Unpin`, use `get_pin_mut` instead.
     pub fn get_mut(&mut self) -> &mut W {
         &mut self.inner
     }
 
+    /// Acquires a pinned reference to the underlying writer.
+    ///
+    /// This works even if `W` is not `Unpin`.
+    pub fn get_pin_ref(self: Pin<&Self>) -> Pin<&W> {
+        // This is safe because `inner` is structurally pinned: it is never
+        // moved out of a pinned wrapper, and the wrapper is only `Unpin` if
+        // `W` is.
+        unsafe { self.map_unchecked(|this| &this.inner) }
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// This works even if `W` is not `Unpin`, unlike `get_mut`.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        // See `get_pin_ref` for why this is safe.
+        unsafe { self.map_unchecked_mut(|this| &mut this.inner) }
+    }
+
     /// Consumes this wrapper, returning the underlying writer.
+    ///
+    /// A pinned wrapper can only be consumed this way if `W` is `Unpin`.
     pub fn into_inner(self) -> W {
         self.inner
     }
+
+    /// Returns the bytes written to the underlying writer so far.
+    ///
+    /// See [`PartialWrite::written`](struct.PartialWrite.html#method.written)
+    /// for more.
+    pub fn written(&self) -> &[u8]
+    where
+        W: WrittenBytes,
+    {
+        self.inner.written_bytes()
+    }
+}
+
+// This is separate from the impl above because the `Write` impl only requires
+// `W: Write`.
+impl<W> PartialAsyncWrite<W> {
+    fn notify_would_block(&mut self) {
+        match (&mut self.readiness, &mut self.on_would_block) {
+            (Some(readiness), _) => {
+                readiness.block();
+                // Polling registers this task to be notified on release. If a
+                // release was already queued, make sure this task is rechecked.
+                if !readiness.poll_blocked() {
+                    task::park().unpark();
+                }
+            }
+            (None, Some(f)) => f(),
+            // Make sure this task is rechecked.
+            (None, None) => task::park().unpark(),
+        }
+    }
+
+    fn check_released(&mut self, what: &str) -> io::Result<()> {
+        if self
+            .readiness
+            .as_mut()
+            .map_or(false, Readiness::poll_blocked)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} not released yet, generated by partial-io", what),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<W> PartialAsyncWrite<W>
+where
+    W: Write,
+{
+    fn write_buffered(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.has_buffered_data() {
+            self.drain_send_buffer(Operation::Write)?;
+        }
+        let send_buffer = self.send_buffer.as_mut().expect("send buffer is set");
+        let room = send_buffer.capacity - send_buffer.data.len();
+        if room == 0 && !buf.is_empty() {
+            self.notify_would_block();
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "send buffer full, generated by partial-io",
+            ));
+        }
+        let len = cmp::min(room, buf.len());
+        send_buffer.data.extend_from_slice(&buf[..len]);
+        self.unflushed += len as u64;
+        Ok(len)
+    }
+
+    /// Applies the next op to pass on part of the send buffer to the inner
+    /// writer.
+    fn drain_send_buffer(&mut self, operation: Operation) -> io::Result<()> {
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Err(err)) => {
+                if err == io::ErrorKind::WouldBlock {
+                    self.notify_would_block();
+                }
+                Err(injected_error(err, operation))
+            }
+            _ => {
+                let limit = op_limit(op.as_ref()).unwrap_or(usize::MAX);
+                let data = &mut self.send_buffer.as_mut().expect("send buffer is set").data;
+                let len = cmp::min(limit, data.len());
+                self.inner.write(&data[..len]).map(|n| {
+                    data.drain(..n);
+                    n
+                })
+            }
+        };
+        self.ops.complete(op, res.as_ref().ok().copied());
+        res.map(|_| ())
+    }
+
+    /// Applies a `PartialOp::FlushDrops` op to a flush, discarding the data
+    /// held in the send buffer.
+    fn drop_send_buffer(&mut self) -> io::Result<()> {
+        let op = self.ops.next();
+        if let Some(send_buffer) = &mut self.send_buffer {
+            send_buffer.data.clear();
+        }
+        self.unflushed = 0;
+        self.ops.complete(op, Some(0));
+        Ok(())
+    }
+
+    #[inline]
+    fn has_buffered_data(&self) -> bool {
+        self.send_buffer
+            .as_ref()
+            .map_or(false, |send_buffer| !send_buffer.data.is_empty())
+    }
+}
+
+impl<W> PartialAsyncWrite<W>
+where
+    W: Seek,
+{
+    /// Returns the current position of the inner writer, without consuming an
+    /// op.
+    ///
+    /// See [`PartialRead::position`](struct.PartialRead.html#method.position)
+    /// for more.
+    pub fn position(&mut self) -> io::Result<u64> {
+        self.inner.stream_position()
+    }
 }
 
 impl<W> Write for PartialAsyncWrite<W>
@@ -100,39 +510,102 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.ops.next() {
-            Some(PartialOp::Limited(n)) => {
+        self.ops.check_fail_flag(Operation::Write)?;
+        self.ops.check_poisoned(Operation::Write)?;
+        self.ops.skip_blocks(Operation::Write)?;
+        self.check_released("write")?;
+        if self.send_buffer.is_some() {
+            return self.write_buffered(buf);
+        }
+        if self.ops.fits_buffer_limit(buf.len()) {
+            let res = self.inner.write(buf);
+            if let Ok(n) = res {
+                self.unflushed += n as u64;
+            }
+            return res;
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n)) | Some(PartialOp::LimitedThenErr(n, _)) => {
                 let len = cmp::min(n, buf.len());
                 self.inner.write(&buf[..len])
             }
             Some(PartialOp::Err(err)) => {
                 if err == io::ErrorKind::WouldBlock {
-                    // Make sure this task is rechecked.
-                    task::park().unpark();
+                    self.notify_would_block();
                 }
-                Err(io::Error::new(
-                    err,
-                    "error during write, generated by partial-io",
-                ))
+                Err(injected_error(err, Operation::Write))
+            }
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let len = first_cap(caps, buf.len());
+                self.inner.write(&buf[..len])
+            }
+            Some(PartialOp::DropTail(n)) => write_drop_tail(&mut self.inner, buf, n),
+            Some(PartialOp::BufferTooLarge(max)) => Err(buffer_too_large(max)),
+            Some(PartialOp::Delimiter(delimiter)) => {
+                let len = delimited_len(buf, delimiter);
+                self.inner.write(&buf[..len])
             }
-            Some(PartialOp::Unlimited) | None => self.inner.write(buf),
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => {
+                self.inner.write(buf)
+            }
+        };
+        if let Ok(n) = res {
+            self.unflushed += n as u64;
         }
+        self.ops.complete(op, res.as_ref().ok().copied());
+        res
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        match self.ops.next() {
+        self.ops.check_fail_flag(Operation::Flush)?;
+        self.ops.check_poisoned(Operation::Flush)?;
+        self.ops.skip_blocks(Operation::Flush)?;
+        self.check_released("flush")?;
+        if self.has_buffered_data() {
+            if let Some(PartialOp::FlushDrops) = self.ops.peek() {
+                return self.drop_send_buffer();
+            }
+            self.drain_send_buffer(Operation::Flush)?;
+            if self.has_buffered_data() {
+                self.notify_would_block();
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "send buffer not drained yet, generated by partial-io",
+                ));
+            }
+            let res = self.inner.flush();
+            if res.is_ok() {
+                self.unflushed = 0;
+            }
+            return res;
+        }
+        // A flush doesn't pass on a buffer, so a `BufferTooLarge` op stays in
+        // place for the next write.
+        if self.ops.fits_buffer_limit(0) {
+            let res = self.inner.flush();
+            if res.is_ok() {
+                self.unflushed = 0;
+            }
+            return res;
+        }
+        let op = self.ops.next();
+        let res = match op {
             Some(PartialOp::Err(err)) => {
                 if err == io::ErrorKind::WouldBlock {
-                    // Make sure this task is rechecked.
-                    task::park().unpark();
+                    self.notify_would_block();
                 }
-                Err(io::Error::new(
-                    err,
-                    "error during flush, generated by partial-io",
-                ))
+                Err(injected_error(err, Operation::Flush))
             }
+            Some(PartialOp::FlushDrops) => Ok(()),
             _ => self.inner.flush(),
+        };
+        if res.is_ok() {
+            self.unflushed = 0;
         }
+        self.ops.complete(op, res.as_ref().ok().map(|_| 0));
+        res
     }
 }
 
@@ -140,9 +613,55 @@ impl<W> AsyncWrite for PartialAsyncWrite<W>
 where
     W: AsyncWrite,
 {
-    #[inline]
+    /// Shuts down the inner writer, applying the next op like `flush` does.
+    ///
+    /// `PartialOp::Err` ops fail the shutdown, and any other op lets it
+    /// through. If a send buffer holds data, it is drained first like `flush`
+    /// does, and the shutdown returns `NotReady` until it is empty.
     fn shutdown(&mut self) -> Poll<(), io::Error> {
-        self.inner.shutdown()
+        self.ops.check_fail_flag(Operation::Shutdown)?;
+        self.ops.check_poisoned(Operation::Shutdown)?;
+        self.ops.skip_blocks(Operation::Shutdown)?;
+        if self.enforce_flush_before_shutdown && self.unflushed > 0 {
+            panic!(
+                "partial-io: shutdown called with {} unflushed byte(s)",
+                self.unflushed
+            );
+        }
+        if self.check_released("shutdown").is_err() {
+            return Ok(Async::NotReady);
+        }
+        if self.has_buffered_data() {
+            match self.drain_send_buffer(Operation::Shutdown) {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                res => res?,
+            }
+            if self.has_buffered_data() {
+                self.notify_would_block();
+                return Ok(Async::NotReady);
+            }
+            return self.inner.shutdown();
+        }
+        if self.ops.fits_buffer_limit(0) {
+            return self.inner.shutdown();
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Err(io::ErrorKind::WouldBlock)) => {
+                self.notify_would_block();
+                Ok(Async::NotReady)
+            }
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Shutdown)),
+            _ => self.inner.shutdown(),
+        };
+        let bytes = match res {
+            Ok(Async::Ready(())) => Some(0),
+            _ => None,
+        };
+        self.ops.complete(op, bytes);
+        res
     }
 }
 
@@ -159,13 +678,11 @@ where
 
 impl<W> AsyncRead for PartialAsyncWrite<W> where W: AsyncRead + AsyncWrite {}
 
-impl<W> fmt::Debug for PartialAsyncWrite<W>
-where
-    W: fmt::Debug,
-{
+impl<W> fmt::Debug for PartialAsyncWrite<W> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PartialAsyncWrite")
-            .field("inner", &self.inner)
+            .field("inner", &format_args!("{}", any::type_name::<W>()))
+            .field("ops_consumed", &self.ops.consumed())
             .finish()
     }
 }
@@ -175,11 +692,211 @@ mod tests {
     use super::*;
 
     use std::fs::File;
+    use std::io::Cursor;
+    use std::marker::PhantomPinned;
+
+    use futures::{executor, future, stream, Sink};
+    use tokio_core::reactor::Core;
+    use tokio_io::codec::{FramedWrite, LinesCodec};
 
     use crate::tests::assert_send;
+    use crate::PartialIoError;
+
+    /// A writer that cannot be moved once pinned.
+    struct PinnedWriter {
+        inner: Vec<u8>,
+        _pin: PhantomPinned,
+    }
+
+    impl Write for PinnedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for PinnedWriter {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(().into())
+        }
+    }
 
     #[test]
     fn test_sendable() {
         assert_send::<PartialAsyncWrite<File>>();
     }
+
+    #[test]
+    fn test_pinned_accessors() {
+        let writer = PinnedWriter {
+            inner: Vec::new(),
+            _pin: PhantomPinned,
+        };
+        let partial_writer = Box::pin(PartialAsyncWrite::new(writer, Vec::<PartialOp>::new()));
+        assert!(partial_writer.as_ref().get_pin_ref().inner.is_empty());
+    }
+
+    #[test]
+    fn test_framed_write() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+        ];
+        let writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+        let framed = FramedWrite::new(writer, LinesCodec::new());
+        let lines = vec!["hello", "partial", "world"]
+            .into_iter()
+            .map(String::from);
+
+        let mut core = Core::new().unwrap();
+        let (framed, _) = core
+            .run(framed.send_all(stream::iter_ok::<_, io::Error>(lines)))
+            .unwrap();
+
+        let out = framed.into_inner().into_inner().into_inner();
+        assert_eq!(&out[..], &b"hello\npartial\nworld\n"[..]);
+    }
+
+    #[test]
+    fn test_enforce_flush_before_shutdown() {
+        let writer = Cursor::new(Vec::new());
+        let mut partial_writer = PartialAsyncWrite::new(writer, vec![PartialOp::Limited(3)]);
+        partial_writer.set_enforce_flush_before_shutdown(true);
+
+        assert_eq!(partial_writer.write(b"hello").unwrap(), 3);
+        assert_eq!(partial_writer.write(b"lo").unwrap(), 2);
+        partial_writer.flush().unwrap();
+        assert!(partial_writer.shutdown().unwrap().is_ready());
+    }
+
+    #[test]
+    #[should_panic(expected = "partial-io: shutdown called with 5 unflushed byte(s)")]
+    fn test_enforce_flush_before_shutdown_panics() {
+        let writer = Cursor::new(Vec::new());
+        let mut partial_writer = PartialAsyncWrite::new(writer, Vec::<PartialOp>::new());
+        partial_writer.set_enforce_flush_before_shutdown(true);
+
+        assert_eq!(partial_writer.write(b"hello").unwrap(), 5);
+        let _ = partial_writer.shutdown();
+    }
+
+    #[test]
+    fn test_shutdown_error() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+        partial_writer.expect_all_consumed();
+
+        let mut core = Core::new().unwrap();
+        let err = core
+            .run(futures::future::poll_fn(|| partial_writer.shutdown()))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            err.to_string(),
+            "error during shutdown, generated by partial-io"
+        );
+        let injected = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<PartialIoError>())
+            .unwrap();
+        assert_eq!(injected.operation(), Operation::Shutdown);
+        assert!(partial_writer.shutdown().unwrap().is_ready());
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(0),
+            PartialOp::Limited(2),
+        ];
+        let mut partial_writer =
+            PartialAsyncWrite::with_buffer_capacity(Cursor::new(Vec::new()), ops, 4);
+        partial_writer.expect_all_consumed();
+
+        // This needs to run within a task, since blocking notifies it.
+        let partial_writer = executor::spawn(future::lazy(move || {
+            // The buffer starts out empty, so this doesn't apply an op.
+            assert_eq!(partial_writer.write(b"abcdefgh")?, 4);
+            // Draining the buffer is blocked.
+            let err = partial_writer.write(b"efgh").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+            // Nothing was drained, so the buffer is still full.
+            let err = partial_writer.write(b"efgh").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+            assert!(partial_writer.get_ref().get_ref().is_empty());
+            // Draining two bytes makes room for two more.
+            assert_eq!(partial_writer.write(b"efgh")?, 2);
+            assert_eq!(partial_writer.get_ref().get_ref(), b"ab");
+            // The ops have run out, so everything buffered drains.
+            assert_eq!(partial_writer.write(b"gh")?, 2);
+            assert_eq!(partial_writer.get_ref().get_ref(), b"abcdef");
+            partial_writer.flush()?;
+            Ok::<_, io::Error>(partial_writer)
+        }))
+        .wait_future()
+        .unwrap();
+        assert_eq!(partial_writer.get_ref().get_ref(), b"abcdefgh");
+    }
+
+    #[test]
+    fn test_flush_drops() {
+        let ops = vec![PartialOp::Limited(2), PartialOp::FlushDrops];
+        let mut partial_writer =
+            PartialAsyncWrite::with_buffer_capacity(Cursor::new(Vec::new()), ops, 4);
+        partial_writer.expect_all_consumed();
+
+        assert_eq!(partial_writer.write(b"abcd").unwrap(), 4);
+        // Drains "ab" and buffers "ef".
+        assert_eq!(partial_writer.write(b"efgh").unwrap(), 2);
+        // The flush claims success, but the buffered bytes are gone.
+        partial_writer.flush().unwrap();
+        assert_eq!(partial_writer.get_ref().get_ref(), b"ab");
+    }
+
+    #[test]
+    fn test_flush_keeps_buffer_too_large() {
+        let ops = vec![PartialOp::BufferTooLarge(4), PartialOp::Limited(1)];
+        let mut partial_writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+
+        partial_writer.flush().unwrap();
+        assert_eq!(partial_writer.shutdown().unwrap(), Async::Ready(()));
+        assert_eq!(partial_writer.ops_consumed(), 0);
+        let err = partial_writer.write(&[0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(partial_writer.write(&[0; 8]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_shutdown_drains_send_buffer() {
+        let ops = vec![PartialOp::Limited(1), PartialOp::Limited(1)];
+        let mut partial_writer =
+            PartialAsyncWrite::with_buffer_capacity(Cursor::new(Vec::new()), ops, 16);
+        partial_writer.expect_all_consumed();
+
+        let partial_writer = executor::spawn(future::lazy(move || {
+            assert_eq!(partial_writer.write(b"hello")?, 5);
+            assert!(partial_writer.get_ref().get_ref().is_empty());
+            // Each op drains a single byte, so the shutdown has to wait.
+            assert_eq!(partial_writer.shutdown()?, Async::NotReady);
+            assert_eq!(partial_writer.get_ref().get_ref(), b"h");
+            assert_eq!(partial_writer.shutdown()?, Async::NotReady);
+            assert_eq!(partial_writer.get_ref().get_ref(), b"he");
+            // The ops have run out, so the rest drains and the shutdown
+            // completes.
+            assert_eq!(partial_writer.shutdown()?, Async::Ready(()));
+            Ok::<_, io::Error>(partial_writer)
+        }))
+        .wait_future()
+        .unwrap();
+        assert_eq!(partial_writer.get_ref().get_ref(), b"hello");
+    }
 }
@@ -0,0 +1,76 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains helpers for reading into `bytes` buffers.
+
+use std::io::{self, Read};
+
+use bytes::BytesMut;
+
+/// Reads up to `additional` bytes from `reader`, appending them to `buf`.
+///
+/// Capacity for `additional` bytes is reserved up front, and `buf` only grows
+/// by the number of bytes actually read. When reading through a
+/// [`PartialRead`], its `PartialOp`s therefore control how much of the reserved
+/// region gets filled. On error, `buf` is left unchanged.
+///
+/// Available with the `bytes` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use bytes::BytesMut;
+/// use partial_io::{read_buf_into, PartialOp, PartialRead};
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), vec![PartialOp::Limited(3)]);
+/// let mut buf = BytesMut::new();
+///
+/// assert_eq!(read_buf_into(&mut reader, &mut buf, 16).unwrap(), 3);
+/// assert_eq!(&buf[..], &[1, 2, 3]);
+/// ```
+///
+/// [`PartialRead`]: struct.PartialRead.html
+pub fn read_buf_into<R>(reader: &mut R, buf: &mut BytesMut, additional: usize) -> io::Result<usize>
+where
+    R: Read + ?Sized,
+{
+    let start = buf.len();
+    buf.resize(start + additional, 0);
+    let res = reader.read(&mut buf[start..]);
+    buf.truncate(start + *res.as_ref().unwrap_or(&0));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{PartialOp, PartialRead};
+
+    #[test]
+    fn test_read_buf_into() {
+        let mut reader = PartialRead::new(
+            Cursor::new((0..16).collect::<Vec<u8>>()),
+            vec![
+                PartialOp::Limited(2),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+            ],
+        );
+        let mut buf = BytesMut::from(&b"xy"[..]);
+
+        assert_eq!(read_buf_into(&mut reader, &mut buf, 8).unwrap(), 2);
+        assert_eq!(&buf[..], &[b'x', b'y', 0, 1]);
+
+        let err = read_buf_into(&mut reader, &mut buf, 8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(buf.len(), 4);
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains support for using `PartialAsyncRead` and `copy` from
+//! both futures 0.1 and futures 0.3 code during a migration.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future;
+use futures03::compat::Compat01As03;
+use futures03::Future;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use crate::{Copy, CopyStats, PartialAsyncRead};
+
+/// Reads through the same ops as the `tokio-io` 0.1 `AsyncRead` impl.
+///
+/// Available with the `compat` feature.
+///
+/// The read runs in a futures 0.1 task that wakes up the futures 0.3 task, so
+/// `WouldBlock` ops, releases set up with `with_releases` and inner readers
+/// built on futures 0.1 all work as they do for futures 0.1 code.
+impl<R> futures03::io::AsyncRead for PartialAsyncRead<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut read = Compat01As03::new(future::poll_fn(|| AsyncRead::poll_read(this, buf)));
+        Pin::new(&mut read).poll(cx)
+    }
+}
+
+/// Lets `copy(reader, writer).await` work from futures 0.3 code.
+///
+/// Available with the `compat` feature.
+impl<R, W> Future for Copy<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    type Output = io::Result<(CopyStats, R, W)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut copy = Compat01As03::new(future::poll_fn(|| future::Future::poll(this)));
+        Pin::new(&mut copy).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use futures::executor;
+    use futures03::io::AsyncReadExt;
+
+    use crate::{copy, Compat, PartialAsyncRead, PartialAsyncWrite, PartialOp};
+
+    #[test]
+    fn test_both_trait_sets() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_reader =
+            PartialAsyncRead::new(Cursor::new((0..16).collect::<Vec<u8>>()), ops);
+        partial_reader.expect_all_consumed();
+        let mut out = vec![0; 8];
+
+        // Through futures 0.1 and tokio-io 0.1.
+        let size = executor::spawn(futures::future::poll_fn(|| {
+            tokio_io::AsyncRead::poll_read(&mut partial_reader, &mut out)
+        }))
+        .wait_future()
+        .unwrap();
+        assert_eq!(&out[..size], &[0, 1]);
+
+        // Then through futures 0.3, which skips over the WouldBlock.
+        let size = futures03::executor::block_on(partial_reader.read(&mut out)).unwrap();
+        assert_eq!(&out[..size], &[2, 3, 4]);
+    }
+
+    #[test]
+    fn test_compat_inner() {
+        let inner = Compat::new(futures03::io::Cursor::new(vec![1, 2, 3, 4]));
+        let partial_reader = PartialAsyncRead::new(inner, vec![PartialOp::Limited(3)]);
+
+        let (_, out, size) = executor::spawn(tokio_io::io::read(partial_reader, vec![0; 8]))
+            .wait_future()
+            .unwrap();
+        assert_eq!(&out[..size], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_copy_await() {
+        let reader = PartialAsyncRead::new(
+            Cursor::new(vec![1, 2, 3, 4]),
+            vec![
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Limited(3),
+            ],
+        );
+        let writer = PartialAsyncWrite::new(
+            Cursor::new(Vec::new()),
+            vec![
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+            ],
+        );
+
+        let (stats, _, writer) =
+            futures03::executor::block_on(async { copy(reader, writer).await }).unwrap();
+        assert_eq!(stats.bytes, 4);
+        assert_eq!(writer.into_inner().into_inner(), vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains a future that copies data from an `AsyncRead` to an
+//! `AsyncWrite`, driving partial and blocked operations to completion.
+
+use std::fmt;
+use std::io;
+
+use futures::{Async, Future, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use crate::DEFAULT_TRANSFER_BUF_SIZE;
+
+/// Statistics about a completed [`copy`].
+///
+/// [`copy`]: fn.copy.html
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CopyStats {
+    /// The total number of bytes copied.
+    pub bytes: u64,
+    /// The number of times the future was polled.
+    pub polls: usize,
+    /// The number of calls to `poll_read`, including ones that failed, returned
+    /// EOF or weren't ready.
+    pub read_calls: usize,
+    /// The number of calls to `poll_write`, including ones that failed or
+    /// weren't ready.
+    pub write_calls: usize,
+    /// The number of times the future waited for the reader to become ready.
+    pub read_pending: usize,
+    /// The number of times the future waited for the writer to become ready,
+    /// including while flushing.
+    pub write_pending: usize,
+}
+
+/// A future that copies all data from a reader to a writer, then flushes the
+/// writer.
+///
+/// This is created by [`copy`].
+///
+/// [`copy`]: fn.copy.html
+pub struct Copy<R, W> {
+    reader: Option<R>,
+    writer: Option<W>,
+    read_done: bool,
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    stats: CopyStats,
+}
+
+/// Creates a future that copies all data from `reader` to `writer`, then
+/// flushes `writer`.
+///
+/// Available with the `tokio` feature.
+///
+/// This is the async counterpart of [`transfer`]. Short reads and writes are
+/// driven to completion and `Interrupted` errors are retried. If either side
+/// isn't ready, the future waits for it to be: in particular, if the writer
+/// blocks while data is buffered, no more is read until all of it has been
+/// written. The future resolves to the stats along with the reader and the
+/// writer.
+///
+/// With the `compat` feature, the future can also be awaited directly from
+/// futures 0.3 code.
+///
+/// [`transfer`]: fn.transfer.html
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Cursor};
+///
+/// use tokio_core::reactor::Core;
+///
+/// use partial_io::{copy, PartialAsyncRead, PartialAsyncWrite, PartialOp};
+///
+/// let data = b"Hello, world!".to_vec();
+/// let read_ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(4)];
+/// let reader = PartialAsyncRead::new(Cursor::new(data.clone()), read_ops);
+/// let write_ops = vec![PartialOp::Limited(3), PartialOp::Err(io::ErrorKind::WouldBlock)];
+/// let writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), write_ops);
+///
+/// let mut core = Core::new().unwrap();
+/// let (stats, _reader, writer) = core.run(copy(reader, writer)).unwrap();
+/// assert_eq!(stats.bytes, 13);
+/// assert_eq!(writer.into_inner().into_inner(), data);
+/// ```
+pub fn copy<R, W>(reader: R, writer: W) -> Copy<R, W>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    Copy {
+        reader: Some(reader),
+        writer: Some(writer),
+        read_done: false,
+        buf: vec![0; DEFAULT_TRANSFER_BUF_SIZE].into_boxed_slice(),
+        pos: 0,
+        cap: 0,
+        stats: CopyStats::default(),
+    }
+}
+
+impl<R, W> Copy<R, W> {
+    /// Returns the stats so far.
+    pub fn stats(&self) -> CopyStats {
+        self.stats
+    }
+}
+
+impl<R, W> Future for Copy<R, W>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    type Item = (CopyStats, R, W);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, io::Error> {
+        self.stats.polls += 1;
+        let reader = self.reader.as_mut().expect("poll a Copy after it's done");
+        let writer = self.writer.as_mut().expect("poll a Copy after it's done");
+
+        loop {
+            // Only read more once everything read so far has been written.
+            if self.pos == self.cap && !self.read_done {
+                self.stats.read_calls += 1;
+                match reader.poll_read(&mut self.buf) {
+                    Ok(Async::Ready(0)) => self.read_done = true,
+                    Ok(Async::Ready(n)) => {
+                        self.pos = 0;
+                        self.cap = n;
+                    }
+                    Ok(Async::NotReady) => {
+                        self.stats.read_pending += 1;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+
+            while self.pos < self.cap {
+                self.stats.write_calls += 1;
+                match writer.poll_write(&self.buf[self.pos..self.cap]) {
+                    Ok(Async::Ready(0)) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        ))
+                    }
+                    Ok(Async::Ready(n)) => {
+                        self.pos += n;
+                        self.stats.bytes += n as u64;
+                    }
+                    Ok(Async::NotReady) => {
+                        self.stats.write_pending += 1;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            if self.read_done {
+                match writer.poll_flush() {
+                    Ok(Async::Ready(())) => break,
+                    Ok(Async::NotReady) => {
+                        self.stats.write_pending += 1;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        let reader = self.reader.take().unwrap();
+        let writer = self.writer.take().unwrap();
+        Ok(Async::Ready((self.stats, reader, writer)))
+    }
+}
+
+impl<R, W> fmt::Debug for Copy<R, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Copy")
+            .field("read_done", &self.read_done)
+            .field("buffered", &(self.cap - self.pos))
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use tokio_core::reactor::Core;
+
+    use crate::{PartialAsyncRead, PartialAsyncWrite, PartialOp};
+
+    #[test]
+    fn test_copy_interleaved_blocks() {
+        let data: Vec<u8> = (0..100).collect();
+        let read_ops = vec![
+            PartialOp::Limited(10),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(30),
+        ];
+        // The writer blocks while the reader still has data buffered.
+        let write_ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(7),
+            PartialOp::Unlimited,
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut reader = PartialAsyncRead::new(Cursor::new(data.clone()), read_ops);
+        reader.expect_all_consumed();
+        let mut writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), write_ops);
+        writer.expect_all_consumed();
+
+        let mut core = Core::new().unwrap();
+        let (stats, _reader, writer) = core.run(copy(reader, writer)).unwrap();
+        assert_eq!(writer.into_inner().into_inner(), data);
+        assert_eq!(stats.bytes, 100);
+        assert_eq!(stats.read_pending, 1);
+        assert_eq!(stats.write_pending, 3);
+        assert_eq!(stats.polls, 5);
+        // 10 bytes, WouldBlock, Interrupted, 30 bytes, then 60 bytes and EOF.
+        assert_eq!(stats.read_calls, 6);
+    }
+
+    #[test]
+    fn test_copy_error() {
+        let reader = PartialAsyncRead::new(
+            Cursor::new(vec![1, 2, 3]),
+            vec![PartialOp::Limited(1), PartialOp::Err(io::ErrorKind::Other)],
+        );
+        let writer = PartialAsyncWrite::new(Cursor::new(Vec::new()), Vec::<PartialOp>::new());
+
+        let mut core = Core::new().unwrap();
+        let err = core.run(copy(reader, writer)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains an in-memory duplex pipe whose ends break their reads
+//! and writes up according to provided iterators.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::observer::OpObserver;
+use crate::ops::{
+    buffer_too_large, delimited_len, first_cap, injected_error, Operation, Ops, PartialIoStats,
+};
+use crate::PartialOp;
+
+/// One end of a synchronous, in-memory duplex pipe.
+///
+/// Bytes written to one end become readable on the other. Each end has its own
+/// sequence of `PartialOp`s, which is consulted on every `read`, `write` and
+/// `flush` call made on that end.
+///
+/// This is meant for testing blocking protocol implementations: hand one end
+/// to a server and the other to a client, possibly on different threads.
+///
+/// # EOF semantics
+///
+/// * A `read` on an end with no buffered data blocks until the other end
+///   writes something or is dropped.
+/// * Once the other end has been dropped, reads return whatever data is still
+///   buffered, and then `Ok(0)` to signal EOF.
+/// * Writes to an end whose peer has been dropped fail with
+///   `ErrorKind::BrokenPipe`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// use partial_io::{InMemoryDuplex, PartialOp};
+///
+/// let (mut client, mut server) = InMemoryDuplex::pair(vec![PartialOp::Unlimited], vec![PartialOp::Limited(2)]);
+/// client.write_all(b"ping").unwrap();
+///
+/// let mut out = vec![0; 256];
+/// assert_eq!(server.read(&mut out).unwrap(), 2);
+/// assert_eq!(&out[..2], b"pi");
+/// ```
+pub struct InMemoryDuplex {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    ops: Ops,
+}
+
+impl InMemoryDuplex {
+    /// Creates both ends of a new pipe, with the specified `PartialOp`s for
+    /// each end.
+    pub fn pair<I, J>(a_iter: I, b_iter: J) -> (Self, Self)
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+        J: IntoIterator + 'static,
+        J::Item: Into<PartialOp>,
+        J::IntoIter: Send,
+    {
+        let a_to_b = Arc::new(Channel::default());
+        let b_to_a = Arc::new(Channel::default());
+        let a = InMemoryDuplex {
+            incoming: b_to_a.clone(),
+            outgoing: a_to_b.clone(),
+            ops: Ops::new(a_iter),
+        };
+        let b = InMemoryDuplex {
+            incoming: a_to_b,
+            outgoing: b_to_a,
+            ops: Ops::new(b_iter),
+        };
+        (a, b)
+    }
+
+    /// Sets the `PartialOp`s for this end.
+    pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.ops.set(iter);
+        self
+    }
+
+    /// Adds an observer to notify about every op this end applies.
+    ///
+    /// Any number of observers can be added, and they coexist with recording.
+    /// See [`OpObserver`] for more.
+    ///
+    /// [`OpObserver`]: trait.OpObserver.html
+    pub fn add_observer(&mut self, observer: impl OpObserver + 'static) -> &mut Self {
+        self.ops.add_observer(Box::new(observer));
+        self
+    }
+
+    /// Skips up to `n` ops without performing any IO, returning how many were
+    /// actually skipped.
+    ///
+    /// See [`PartialRead::skip_ops`](struct.PartialRead.html#method.skip_ops)
+    /// for more.
+    pub fn skip_ops(&mut self, n: usize) -> usize {
+        self.ops.skip_ops(n)
+    }
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    pub fn ops_consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns counts of the `PartialOp`s applied so far, by kind.
+    ///
+    /// See [`PartialIoStats`](struct.PartialIoStats.html) for more.
+    pub fn stats(&self) -> PartialIoStats {
+        self.ops.stats()
+    }
+
+    /// Panics when this end is dropped if any of its `PartialOp`s were
+    /// never consumed.
+    ///
+    /// See [`PartialRead::expect_all_consumed`](struct.PartialRead.html#method.expect_all_consumed)
+    /// for more.
+    pub fn expect_all_consumed(&mut self) -> &mut Self {
+        self.ops.expect_all_consumed();
+        self
+    }
+}
+
+impl Read for InMemoryDuplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.ops.fits_buffer_limit(buf.len()) {
+            return self.incoming.read(buf);
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n))
+            | Some(PartialOp::LimitedThenErr(n, _))
+            | Some(PartialOp::DropTail(n)) => {
+                let len = cmp::min(n, buf.len());
+                self.incoming.read(&mut buf[..len])
+            }
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Read)),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let len = first_cap(caps, buf.len());
+                self.incoming.read(&mut buf[..len])
+            }
+            Some(PartialOp::BufferTooLarge(max)) => Err(buffer_too_large(max)),
+            Some(PartialOp::Delimiter(delimiter)) => self.incoming.read_until(buf, Some(delimiter)),
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => {
+                self.incoming.read(buf)
+            }
+        };
+        self.ops.complete(op, res.as_ref().ok().copied());
+        res
+    }
+}
+
+impl Write for InMemoryDuplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.ops.fits_buffer_limit(buf.len()) {
+            return self.outgoing.write(buf);
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n)) | Some(PartialOp::LimitedThenErr(n, _)) => {
+                let len = cmp::min(n, buf.len());
+                self.outgoing.write(&buf[..len])
+            }
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Write)),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let len = first_cap(caps, buf.len());
+                self.outgoing.write(&buf[..len])
+            }
+            Some(PartialOp::DropTail(n)) => {
+                // Channel writes are never short.
+                let len = cmp::min(n, buf.len());
+                self.outgoing.write(&buf[..len]).map(|_| buf.len())
+            }
+            Some(PartialOp::BufferTooLarge(max)) => Err(buffer_too_large(max)),
+            Some(PartialOp::Delimiter(delimiter)) => {
+                let len = delimited_len(buf, delimiter);
+                self.outgoing.write(&buf[..len])
+            }
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => {
+                self.outgoing.write(buf)
+            }
+        };
+        self.ops.complete(op, res.as_ref().ok().copied());
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // A flush doesn't pass on a buffer, so a `BufferTooLarge` op stays in
+        // place for the next write.
+        if self.ops.fits_buffer_limit(0) {
+            return Ok(());
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Flush)),
+            // Writes are immediately visible to the other end.
+            _ => Ok(()),
+        };
+        self.ops.complete(op, res.as_ref().ok().map(|_| 0));
+        res
+    }
+}
+
+impl Drop for InMemoryDuplex {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl fmt::Debug for InMemoryDuplex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryDuplex").finish()
+    }
+}
+
+/// A unidirectional buffer shared between the two ends of a pipe.
+#[derive(Default)]
+struct Channel {
+    state: Mutex<ChannelState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    buf: VecDeque<u8>,
+    // Set once either end of the pipe is dropped.
+    closed: bool,
+}
+
+impl Channel {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_until(buf, None)
+    }
+
+    /// Reads like `read`, but stops right after the first occurrence of
+    /// `delimiter`, if any.
+    fn read_until(&self, buf: &mut [u8], delimiter: Option<u8>) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.state.lock().unwrap();
+        while state.buf.is_empty() && !state.closed {
+            state = self.readable.wait(state).unwrap();
+        }
+        let mut len = cmp::min(buf.len(), state.buf.len());
+        if let Some(delimiter) = delimiter {
+            if let Some(pos) = state.buf.iter().take(len).position(|&b| b == delimiter) {
+                len = pos + 1;
+            }
+        }
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "other end of the duplex was dropped",
+            ));
+        }
+        state.buf.extend(buf);
+        self.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::tests::assert_send;
+
+    #[test]
+    fn test_sendable() {
+        assert_send::<InMemoryDuplex>();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let client_ops = vec![PartialOp::Limited(1), PartialOp::Limited(2)];
+        let server_ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+        ];
+        let (mut client, mut server) = InMemoryDuplex::pair(client_ops, server_ops);
+
+        // Echo everything back until the client hangs up.
+        let server = thread::spawn(move || {
+            let mut buf = vec![0; 256];
+            let mut total = 0;
+            loop {
+                let n = match server.read(&mut buf) {
+                    Ok(0) => return total,
+                    Ok(n) => n,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => panic!("unexpected error: {}", err),
+                };
+                server.write_all(&buf[..n]).unwrap();
+                total += n;
+            }
+        });
+
+        client.write_all(b"hello, world").unwrap();
+        let mut out = vec![0; 12];
+        client.read_exact(&mut out).unwrap();
+        assert_eq!(&out, b"hello, world");
+
+        // Dropping the client signals EOF to the server.
+        drop(client);
+        assert_eq!(server.join().unwrap(), 12);
+    }
+
+    #[test]
+    fn test_dropped_peer() {
+        let (mut a, mut b) = InMemoryDuplex::pair(Vec::<PartialOp>::new(), Vec::<PartialOp>::new());
+        a.write_all(b"bye").unwrap();
+        drop(a);
+
+        // Buffered data is still readable, followed by EOF.
+        let mut out = Vec::new();
+        b.read_to_end(&mut out).unwrap();
+        assert_eq!(&out, b"bye");
+        assert_eq!(b.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,708 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains helpers that generate common sequences of `PartialOp`s.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::iter;
+use std::sync::{Arc, Mutex};
+
+use crate::{PartialOp, DEFAULT_TRANSFER_BUF_SIZE};
+
+/// Generates runs of `WouldBlock` errors separated by single successful
+/// operations, cycling through `counts`.
+///
+/// Each entry in `counts` produces that many consecutive
+/// `PartialOp::Err(ErrorKind::WouldBlock)` ops followed by one
+/// `PartialOp::Unlimited`. Once `counts` is exhausted, it starts over from the
+/// beginning, so the sequence never ends unless `counts` is empty.
+///
+/// This is useful for driving retry loops with backoff through a precise
+/// schedule.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{backoff_block, PartialOp};
+///
+/// let ops: Vec<_> = backoff_block(&[2, 0]).take(5).collect();
+/// assert_eq!(
+///     ops,
+///     vec![
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///     ]
+/// );
+/// ```
+pub fn backoff_block(counts: &[usize]) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    let counts = counts.to_vec();
+    counts.into_iter().cycle().flat_map(|count| {
+        iter::repeat(PartialOp::Err(io::ErrorKind::WouldBlock))
+            .take(count)
+            .chain(iter::once(PartialOp::Unlimited))
+    })
+}
+
+/// Applies `op` to the first operation only, after which the wrapper forwards
+/// every call unmodified.
+///
+/// Many bugs live in the code path for the first read or write, e.g. handshake
+/// or header parsing. This is the same as `iter::once(op)`, but names the
+/// intent.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{first, PartialOp, PartialRead};
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), first(PartialOp::Limited(1)));
+/// let mut out = vec![0; 256];
+/// assert_eq!(reader.read(&mut out).unwrap(), 1);
+/// assert_eq!(reader.read(&mut out).unwrap(), 3);
+/// ```
+pub fn first(op: PartialOp) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    iter::once(op)
+}
+
+/// Applies `op` to every `n`th operation, starting with operation `offset`,
+/// and lets all other operations through with `PartialOp::Unlimited`.
+///
+/// Operations are counted from 0, so `op` applies to operation `i` if
+/// `i >= offset` and `(i - offset) % n == 0`. For example, `every(3, 2, op)`
+/// applies `op` to operations 2, 5, 8 and so on. The sequence never ends.
+///
+/// # Panics
+///
+/// Panics if `n` is 0.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{every, PartialOp};
+///
+/// let block = PartialOp::Err(io::ErrorKind::WouldBlock);
+/// let ops: Vec<_> = every(2, 1, block.clone()).take(4).collect();
+/// assert_eq!(
+///     ops,
+///     vec![PartialOp::Unlimited, block.clone(), PartialOp::Unlimited, block]
+/// );
+/// ```
+pub fn every(
+    n: usize,
+    offset: usize,
+    op: PartialOp,
+) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    assert!(n > 0, "every: n must be nonzero");
+    (0..).map(move |i: usize| {
+        if i >= offset && (i - offset) % n == 0 {
+            op.clone()
+        } else {
+            PartialOp::Unlimited
+        }
+    })
+}
+
+/// Yields `ops` in reverse order.
+///
+/// This is for symmetry tests, e.g. applying a script to the write side of a
+/// round trip and its reverse to the read side, to check that the round trip
+/// doesn't depend on the order of the ops.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{reverse, PartialOp};
+///
+/// let ops: Vec<_> = reverse(vec![PartialOp::Limited(1), PartialOp::Unlimited]).collect();
+/// assert_eq!(ops, vec![PartialOp::Unlimited, PartialOp::Limited(1)]);
+/// ```
+pub fn reverse(ops: Vec<PartialOp>) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    ops.into_iter().rev()
+}
+
+/// Generates `Limited` ops that each cap an operation at `pct` percent of the
+/// bytes remaining out of `total`, assuming that every op transfers its full
+/// limit.
+///
+/// The generator doesn't see how many bytes were actually transferred: it
+/// subtracts each limit from the remaining bytes as it hands out the op. If an
+/// operation transfers less, e.g. because the buffer is smaller than the limit
+/// or the inner reader returns short reads, the limits shrink faster than the
+/// data does, and the generator ends early. It only matches the data for
+/// sources of known length that fill every read, e.g. a `Cursor` read into a
+/// large enough buffer. Limits are rounded up, so they don't drop to zero before all `total` bytes
+/// have been accounted for, at which point the generator ends. This models a
+/// transport that delivers a shrinking fraction of the data as it drains.
+///
+/// # Panics
+///
+/// Panics if `pct` is 0 or greater than 100.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{percent_of_remaining_assuming_full, PartialOp};
+///
+/// let ops: Vec<_> = percent_of_remaining_assuming_full(100, 25).take(3).collect();
+/// assert_eq!(
+///     ops,
+///     vec![PartialOp::Limited(25), PartialOp::Limited(19), PartialOp::Limited(14)]
+/// );
+/// ```
+pub fn percent_of_remaining_assuming_full(
+    total: u64,
+    pct: u32,
+) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    assert!(
+        pct > 0 && pct <= 100,
+        "percent_of_remaining_assuming_full: pct must be between 1 and 100, got {}",
+        pct
+    );
+    let mut remaining = total;
+    iter::from_fn(move || {
+        if remaining == 0 {
+            return None;
+        }
+        // Round up so that the limit never drops to zero early. This can't
+        // overflow because the product is computed in 128 bits.
+        let limit = ((remaining as u128 * pct as u128 + 99) / 100) as u64;
+        remaining -= limit;
+        Some(PartialOp::Limited(
+            usize::try_from(limit).unwrap_or(usize::MAX),
+        ))
+    })
+}
+
+/// Generates `Limited` ops that make reads stop exactly at each of the given
+/// absolute byte `offsets`, e.g. at record boundaries.
+///
+/// Each op limits the next read to the gap up to the next offset, so a read
+/// never spans an offset. Once the last offset has been reached, the generator
+/// ends and reads are unlimited.
+///
+/// Reads into buffers smaller than a gap only use part of the op, so use this
+/// with [`PartialRead::set_strict_limit`] to carry the rest of the gap over to
+/// the following reads and keep the offsets exact.
+///
+/// # Panics
+///
+/// Panics if `offsets` isn't strictly increasing or contains 0.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{split_at, PartialRead};
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![0; 20]), split_at(vec![4, 10, 11]));
+/// reader.set_strict_limit(true);
+///
+/// let mut out = vec![0; 16];
+/// assert_eq!(reader.read(&mut out).unwrap(), 4);
+/// assert_eq!(reader.read(&mut out).unwrap(), 6);
+/// assert_eq!(reader.read(&mut out).unwrap(), 1);
+/// assert_eq!(reader.read(&mut out).unwrap(), 9);
+/// ```
+///
+/// [`PartialRead::set_strict_limit`]: struct.PartialRead.html#method.set_strict_limit
+pub fn split_at(offsets: Vec<u64>) -> impl Iterator<Item = PartialOp> + Send + 'static {
+    let mut prev = 0;
+    let gaps: Vec<_> = offsets
+        .into_iter()
+        .map(|offset| {
+            assert!(
+                offset > prev,
+                "split_at: offsets must be strictly increasing and nonzero, got {} after {}",
+                offset,
+                prev
+            );
+            let gap = offset - prev;
+            prev = offset;
+            PartialOp::Limited(gap as usize)
+        })
+        .collect();
+    gaps.into_iter()
+}
+
+/// Reads `reader` to the end and returns the sizes of the reads it naturally
+/// produced, as `PartialOp::Limited` ops.
+///
+/// Replaying the result on another reader reproduces a realistic schedule of
+/// partial reads, e.g. the chunks a `BufReader` or a network socket hands out,
+/// rather than an adversarial one. Reads are made with a buffer of
+/// [`DEFAULT_TRANSFER_BUF_SIZE`] bytes, so longer reads are split up, and
+/// `Interrupted` errors are retried without being recorded. This consumes
+/// `reader`; pass `&mut reader` to keep using it, though it will be at the end
+/// by then.
+///
+/// # Errors
+///
+/// Returns the first error other than `Interrupted` that `reader` returns.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use partial_io::{from_read_trace, PartialOp};
+///
+/// let trace = (&b"abc"[..]).chain(&b"defgh"[..]);
+/// let ops = from_read_trace(trace).unwrap();
+/// assert_eq!(ops, vec![PartialOp::Limited(3), PartialOp::Limited(5)]);
+/// ```
+///
+/// [`DEFAULT_TRANSFER_BUF_SIZE`]: constant.DEFAULT_TRANSFER_BUF_SIZE.html
+pub fn from_read_trace<R>(mut reader: R) -> io::Result<Vec<PartialOp>>
+where
+    R: Read,
+{
+    let mut buf = vec![0; DEFAULT_TRANSFER_BUF_SIZE];
+    let mut ops = Vec::new();
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(ops),
+            Ok(n) => ops.push(PartialOp::Limited(n)),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// An infinite source of `PartialOp::Delimiter` ops, which make every
+/// operation stop right after the given delimiter byte.
+///
+/// This models a transport that delivers one line or record at a time, to
+/// check that code doesn't assume that several of them arrive together. See
+/// [`PartialOp::Delimiter`](enum.PartialOp.html#variant.Delimiter) for how
+/// reads find the delimiter.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{DelimiterOps, PartialRead};
+///
+/// let mut reader = PartialRead::new(Cursor::new(b"one\ntwo\n".to_vec()), DelimiterOps::new(b'\n'));
+/// let mut out = vec![0; 256];
+/// assert_eq!(reader.read(&mut out).unwrap(), 4);
+/// assert_eq!(&out[..4], b"one\n");
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DelimiterOps {
+    delimiter: u8,
+}
+
+impl DelimiterOps {
+    /// Creates a new source of ops that stop at `delimiter`.
+    #[inline]
+    pub fn new(delimiter: u8) -> Self {
+        DelimiterOps { delimiter }
+    }
+}
+
+impl Iterator for DelimiterOps {
+    type Item = PartialOp;
+
+    #[inline]
+    fn next(&mut self) -> Option<PartialOp> {
+        Some(PartialOp::Delimiter(self.delimiter))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// A step in a combined timeline of reads and writes, for use with
+/// [`split_events`].
+///
+/// [`split_events`]: fn.split_events.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The next read applies this op.
+    Read(PartialOp),
+    /// The next write applies this op.
+    Write(PartialOp),
+}
+
+/// Splits a single timeline of events into separate read and write ops.
+///
+/// The first iterator returned yields the ops of every `Event::Read`, and the
+/// second one those of every `Event::Write`, each in the order they appear in
+/// `events`. This allows the two sides of a duplex or a `transfer` to be
+/// scripted as one coherent timeline, e.g. so that every blocked read is
+/// followed by a blocked write.
+///
+/// `events` is consumed lazily, so it may be infinite. Events pulled by one
+/// side on behalf of the other are buffered until the other side asks for
+/// them.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{split_events, Event, PartialOp};
+///
+/// let events = vec![
+///     Event::Read(PartialOp::Err(io::ErrorKind::WouldBlock)),
+///     Event::Write(PartialOp::Err(io::ErrorKind::WouldBlock)),
+///     Event::Read(PartialOp::Limited(2)),
+/// ];
+/// let (read_ops, write_ops) = split_events(events);
+/// assert_eq!(
+///     read_ops.collect::<Vec<_>>(),
+///     vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(2)]
+/// );
+/// assert_eq!(
+///     write_ops.collect::<Vec<_>>(),
+///     vec![PartialOp::Err(io::ErrorKind::WouldBlock)]
+/// );
+/// ```
+pub fn split_events<I>(
+    events: I,
+) -> (
+    impl Iterator<Item = PartialOp> + Send + 'static,
+    impl Iterator<Item = PartialOp> + Send + 'static,
+)
+where
+    I: IntoIterator<Item = Event> + 'static,
+    I::IntoIter: Send,
+{
+    let shared = Arc::new(Mutex::new(SplitEvents {
+        events: Box::new(events.into_iter().fuse()),
+        reads: VecDeque::new(),
+        writes: VecDeque::new(),
+    }));
+    let reads = SplitSide {
+        shared: shared.clone(),
+        read: true,
+    };
+    let writes = SplitSide {
+        shared,
+        read: false,
+    };
+    (reads, writes)
+}
+
+struct SplitEvents {
+    events: Box<dyn Iterator<Item = Event> + Send>,
+    reads: VecDeque<PartialOp>,
+    writes: VecDeque<PartialOp>,
+}
+
+struct SplitSide {
+    shared: Arc<Mutex<SplitEvents>>,
+    read: bool,
+}
+
+impl Iterator for SplitSide {
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<PartialOp> {
+        let mut shared = self.shared.lock().expect("split_events state poisoned");
+        let buffered = if self.read {
+            shared.reads.pop_front()
+        } else {
+            shared.writes.pop_front()
+        };
+        if buffered.is_some() {
+            return buffered;
+        }
+        // Pull events until one for this side shows up, buffering the rest.
+        loop {
+            match (shared.events.next()?, self.read) {
+                (Event::Read(op), true) | (Event::Write(op), false) => return Some(op),
+                (Event::Read(op), false) => shared.reads.push_back(op),
+                (Event::Write(op), true) => shared.writes.push_back(op),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufReader, Cursor};
+
+    use crate::PartialRead;
+
+    #[test]
+    fn test_from_read_trace() {
+        let data: Vec<u8> = (0..40).collect();
+        // A read with a buffer larger than the `BufReader`'s own goes straight
+        // to the inner reader, so the chunks it hands out come through.
+        let inner = PartialRead::new(
+            Cursor::new(data.clone()),
+            vec![PartialOp::Limited(3), PartialOp::Limited(9)],
+        );
+        let ops = from_read_trace(BufReader::with_capacity(8, inner)).unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Limited(3),
+                PartialOp::Limited(9),
+                PartialOp::Limited(28),
+            ]
+        );
+
+        let mut reader = PartialRead::new(Cursor::new(data.clone()), ops.clone());
+        let mut out = [0; 64];
+        let mut sizes = Vec::new();
+        let mut total = 0;
+        loop {
+            match reader.read(&mut out[total..]).unwrap() {
+                0 => break,
+                n => {
+                    sizes.push(PartialOp::Limited(n));
+                    total += n;
+                }
+            }
+        }
+        assert_eq!(sizes, ops);
+        assert_eq!(&out[..total], &data[..]);
+    }
+
+    #[test]
+    fn test_delimiter_ops() {
+        let data = b"first\nsecond\n\nlast".to_vec();
+        let mut reader = PartialRead::new(Cursor::new(data), DelimiterOps::new(b'\n'));
+        let mut out = [0; 64];
+        let mut lines = Vec::new();
+        loop {
+            let n = reader.read(&mut out).unwrap();
+            if n == 0 {
+                break;
+            }
+            lines.push(out[..n].to_vec());
+        }
+        assert_eq!(
+            lines,
+            vec![
+                b"first\n".to_vec(),
+                b"second\n".to_vec(),
+                b"\n".to_vec(),
+                b"last".to_vec()
+            ]
+        );
+
+        // A small buffer still stops at the end of the buffer.
+        let mut reader = PartialRead::new(Cursor::new(b"abc\n".to_vec()), DelimiterOps::new(b'\n'));
+        assert_eq!(reader.read(&mut out[..2]).unwrap(), 2);
+        assert_eq!(reader.read(&mut out).unwrap(), 2);
+        assert_eq!(&out[..2], b"c\n");
+    }
+
+    #[test]
+    fn test_every() {
+        let block = PartialOp::Err(io::ErrorKind::WouldBlock);
+        let blocked: Vec<_> = every(3, 2, block.clone())
+            .take(10)
+            .enumerate()
+            .filter(|(_, op)| *op == block)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(blocked, vec![2, 5, 8]);
+    }
+
+    #[test]
+    #[should_panic(expected = "every: n must be nonzero")]
+    fn test_every_zero() {
+        let _ = every(0, 0, PartialOp::Unlimited);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let block = PartialOp::Err(io::ErrorKind::WouldBlock);
+        let ops = vec![PartialOp::Limited(1), block.clone(), PartialOp::Limited(3)];
+        assert_eq!(
+            reverse(ops).collect::<Vec<_>>(),
+            vec![PartialOp::Limited(3), block, PartialOp::Limited(1)]
+        );
+    }
+
+    #[test]
+    fn test_backoff_block() {
+        let block = PartialOp::Err(io::ErrorKind::WouldBlock);
+        let ok = PartialOp::Unlimited;
+        let ops: Vec<_> = backoff_block(&[3, 1, 0]).take(14).collect();
+        assert_eq!(
+            ops,
+            vec![
+                block.clone(),
+                block.clone(),
+                block.clone(),
+                ok.clone(),
+                block.clone(),
+                ok.clone(),
+                ok.clone(),
+                // The counts start over.
+                block.clone(),
+                block.clone(),
+                block.clone(),
+                ok.clone(),
+                block,
+                ok.clone(),
+                ok,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_first() {
+        let mut reader = PartialRead::new(
+            Cursor::new((0..32).collect::<Vec<u8>>()),
+            first(PartialOp::Err(io::ErrorKind::Interrupted)),
+        );
+        let mut out = vec![0; 8];
+        assert_eq!(
+            reader.read(&mut out).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        // Later calls are forwarded to the inner reader unmodified.
+        assert_eq!(reader.read(&mut out).unwrap(), 8);
+        assert_eq!(&out, &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(reader.read(&mut out).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_backoff_block_empty() {
+        assert_eq!(backoff_block(&[]).next(), None);
+    }
+
+    #[test]
+    fn test_split_events() {
+        let events = vec![
+            Event::Write(PartialOp::Limited(1)),
+            Event::Read(PartialOp::Limited(2)),
+            Event::Write(PartialOp::Limited(3)),
+            Event::Write(PartialOp::Limited(4)),
+            Event::Read(PartialOp::Limited(5)),
+        ];
+        let (mut reads, writes) = split_events(events);
+
+        // Pulling the reads first buffers the writes, in order.
+        assert_eq!(reads.next(), Some(PartialOp::Limited(2)));
+        assert_eq!(reads.next(), Some(PartialOp::Limited(5)));
+        assert_eq!(reads.next(), None);
+        assert_eq!(
+            writes.collect::<Vec<_>>(),
+            vec![
+                PartialOp::Limited(1),
+                PartialOp::Limited(3),
+                PartialOp::Limited(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_percent_of_remaining_assuming_full() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = PartialRead::new(
+            Cursor::new(data.clone()),
+            percent_of_remaining_assuming_full(100, 25),
+        );
+        let mut out = vec![0; 256];
+        let mut sizes = Vec::new();
+        let mut read = Vec::new();
+        loop {
+            let n = reader.read(&mut out).unwrap();
+            if n == 0 {
+                break;
+            }
+            sizes.push(n);
+            read.extend_from_slice(&out[..n]);
+        }
+        assert_eq!(&sizes[..4], &[25, 19, 14, 11]);
+        assert!(sizes.iter().all(|&n| n > 0));
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn test_percent_of_remaining_assuming_full_short_reads() {
+        // The inner reader never returns more than 3 bytes at a time.
+        let inner = PartialRead::new(
+            Cursor::new((0..100).collect::<Vec<u8>>()),
+            iter::repeat(PartialOp::Limited(3)),
+        );
+        let mut reader = PartialRead::new(inner, percent_of_remaining_assuming_full(100, 50));
+        reader.set_recording(true);
+        let mut out = vec![0; 256];
+        for _ in 0..8 {
+            assert!(reader.read(&mut out).unwrap() > 0);
+        }
+        // The limits assumed full reads, so they ran out after 18 of the 100
+        // bytes.
+        assert_eq!(
+            reader.recorded(),
+            &[
+                (PartialOp::Limited(50), 3),
+                (PartialOp::Limited(25), 3),
+                (PartialOp::Limited(13), 3),
+                (PartialOp::Limited(6), 3),
+                (PartialOp::Limited(3), 3),
+                (PartialOp::Limited(2), 2),
+                (PartialOp::Limited(1), 1),
+                (PartialOp::Unlimited, 3),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "pct must be between 1 and 100")]
+    fn test_percent_of_remaining_assuming_full_zero() {
+        let _ = percent_of_remaining_assuming_full(100, 0);
+    }
+
+    #[test]
+    fn test_split_at() {
+        let read_sizes = |buf_len: usize| {
+            let mut reader = PartialRead::new(
+                Cursor::new((0..20).collect::<Vec<u8>>()),
+                split_at(vec![4, 10, 11]),
+            );
+            reader.set_strict_limit(true);
+            let mut out = vec![0; buf_len];
+            let mut sizes = Vec::new();
+            let mut pos = 0;
+            loop {
+                let n = reader.read(&mut out).unwrap();
+                if n == 0 {
+                    return sizes;
+                }
+                // The data still arrives in order.
+                assert_eq!(out[0], pos as u8);
+                pos += n;
+                sizes.push(n);
+            }
+        };
+
+        assert_eq!(read_sizes(16), vec![4, 6, 1, 9]);
+        // Smaller buffers still stop at every offset.
+        assert_eq!(read_sizes(3), vec![3, 1, 3, 3, 1, 3, 3, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "offsets must be strictly increasing")]
+    fn test_split_at_unordered() {
+        let _ = split_at(vec![4, 4]);
+    }
+}
@@ -17,14 +17,32 @@
 //! * [`PartialRead`] and [`PartialWrite`], which wrap existing `Read` and
 //!   `Write` implementations and allow specifying arbitrary behavior on the
 //!   next `read`, `write` or `flush` call.
+//! * [`InMemoryDuplex`], an in-memory pipe whose ends apply their own
+//!   sequences of operations, for testing blocking protocol implementations.
+//! * [`transfer`], which copies data from a reader to a writer, driving
+//!   partial and interrupted operations to completion, and reports how many
+//!   calls that took.
 //! * With the optional `tokio` feature, [`PartialAsyncRead`] and
 //!   [`PartialAsyncWrite`] to wrap existing `AsyncRead` and `AsyncWrite`
 //!   implementations. These implementations are task-aware, so they will know
 //!   how to pause and unpause tasks if they return a `WouldBlock` error.
+//!   `PartialAsyncWrite` applies ops to `shutdown` calls as well.
+//!   [`copy`] is the async counterpart of [`transfer`].
+//! * With the optional `compat` feature, a futures 0.3 `AsyncRead` impl for
+//!   [`PartialAsyncRead`], so that the same wrapper can be used from futures
+//!   0.1 and 0.3 code while migrating between them.
+//! * [`PartialOpRng`], a seeded generator of random operations for
+//!   reproducible randomized tests.
+//! * With the optional `bytes` feature, [`read_buf_into`] to read into a
+//!   `BytesMut` without dealing with its spare capacity by hand.
 //! * With the optional `quickcheck` feature, generation of random sequences of
 //!   operations which can be provided to one of the wrappers. See the
 //!   [`quickcheck_types` documentation](quickcheck_types/index.html) for more.
 //!
+//! To import all of the commonly used items at once, use
+//! `use partial_io::prelude::*;`. See the [`prelude`](prelude/index.html)
+//! module for more.
+//!
 //! # Motivation
 //!
 //! A `Read` or `Write` wrapper is conceptually simple but can be difficult to
@@ -84,6 +102,11 @@
 //!
 //! [`PartialRead`]: struct.PartialRead.html
 //! [`PartialWrite`]: struct.PartialWrite.html
+//! [`InMemoryDuplex`]: struct.InMemoryDuplex.html
+//! [`transfer`]: fn.transfer.html
+//! [`copy`]: fn.copy.html
+//! [`PartialOpRng`]: struct.PartialOpRng.html
+//! [`read_buf_into`]: fn.read_buf_into.html
 //! [`PartialAsyncRead`]: struct.PartialAsyncRead.html
 //! [`PartialAsyncWrite`]: struct.PartialAsyncWrite.html
 //! [`quickcheck_types`]: quickcheck_types/index.html
@@ -95,37 +118,111 @@
 mod async_read;
 #[cfg(feature = "tokio")]
 mod async_write;
+#[cfg(feature = "bytes")]
+mod bytes_mut;
+#[cfg(feature = "compat")]
+mod compat;
+#[cfg(feature = "tokio")]
+mod copy;
+mod duplex;
+mod generators;
+mod observer;
+mod ops;
+mod parse;
+pub mod prelude;
 #[cfg(feature = "quickcheck")]
 pub mod quickcheck_types;
 mod read;
+#[cfg(feature = "tokio")]
+mod readiness;
+mod rng;
+mod transfer;
+mod wrapper;
 mod write;
 
+use std::fmt;
 use std::io;
 
 #[cfg(feature = "tokio")]
 pub use crate::async_read::PartialAsyncRead;
 #[cfg(feature = "tokio")]
 pub use crate::async_write::PartialAsyncWrite;
+#[cfg(feature = "bytes")]
+pub use crate::bytes_mut::read_buf_into;
+#[cfg(feature = "tokio")]
+pub use crate::copy::{copy, Copy, CopyStats};
+pub use crate::duplex::InMemoryDuplex;
+pub use crate::generators::{
+    backoff_block, every, first, from_read_trace, percent_of_remaining_assuming_full, reverse,
+    split_at, split_events, DelimiterOps, Event,
+};
+pub use crate::observer::{OpObserver, TimingObserver};
+pub use crate::ops::{
+    set_thread_local_ops, BufferTooLargeError, EmptyOps, Operation, PartialIoError, PartialIoStats,
+};
+pub use crate::parse::ParseOpError;
 #[cfg(feature = "quickcheck")]
 pub use crate::quickcheck_types::{
     GenError, GenInterrupted, GenInterruptedWouldBlock, GenNoErrors, GenWouldBlock,
     PartialWithErrors,
 };
-pub use crate::read::PartialRead;
-pub use crate::write::PartialWrite;
+pub use crate::read::{chain, synthetic, PartialRead, Synthetic};
+pub use crate::rng::{PartialOpRng, PartialOpRngBuilder, SEED_ENV_VAR};
+pub use crate::transfer::{
+    assert_equivalent, transfer, transfer_default, validate_script, ScriptError, TransferStats,
+    DEFAULT_TRANSFER_BUF_SIZE,
+};
+pub use crate::wrapper::PartialIo;
+pub use crate::write::{PartialWrite, WrittenBytes};
+/// Adapts a futures 0.3 reader so that it can be wrapped by
+/// [`PartialAsyncRead`](struct.PartialAsyncRead.html).
+///
+/// Available with the `compat` feature.
+#[cfg(feature = "compat")]
+pub use futures03::compat::Compat;
 
 /// What to do the next time an IO operation is performed.
 ///
 /// This is not the same as `io::Result<Option<usize>>` because it contains
 /// `io::ErrorKind` instances, not `io::Error` instances. This allows it to be
 /// clonable.
-#[derive(Clone, Debug)]
+///
+/// This enum is `#[non_exhaustive]`, so that new kinds of ops can be added
+/// without breaking downstream code. Matches on a `PartialOp` outside this
+/// crate need a wildcard arm:
+///
+/// ```rust
+/// use partial_io::PartialOp;
+///
+/// fn describe(op: &PartialOp) -> &'static str {
+///     match op {
+///         PartialOp::Limited(_) => "limited",
+///         PartialOp::Unlimited => "unlimited",
+///         PartialOp::Err(_) => "error",
+///         _ => "other",
+///     }
+/// }
+///
+/// assert_eq!(describe(&PartialOp::limited(4)), "limited");
+/// ```
+///
+/// Variants can still be constructed directly, but the constructor functions,
+/// e.g. `PartialOp::limited(4)` instead of `PartialOp::Limited(4)`, are the
+/// recommended way to do so going forward. Code that builds ops directly keeps
+/// working; only exhaustive matches need to be updated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum PartialOp {
     /// Limit the next IO operation to a certain number of bytes.
     ///
     /// The wrapper will call into the inner `Read` or `Write`
     /// instance. Depending on what the underlying operation does, this may
     /// return an error or a fewer number of bytes.
+    ///
+    /// `Limited(0)` calls into the inner instance with an empty buffer, so a
+    /// read returns `Ok(0)` without consuming any data. Unlike `Eof`, this is
+    /// reported as coming from the inner reader. Vectored reads apply the limit
+    /// to the first non-empty buffer, like a `read` into that buffer would.
     Limited(usize),
 
     /// Do not limit the next IO operation.
@@ -137,18 +234,404 @@ pub enum PartialOp {
 
     /// Return an error instead of calling into the underlying operation.
     Err(io::ErrorKind),
+
+    /// Limit the next IO operation to a certain number of bytes, then fail the
+    /// one after it with an error.
+    ///
+    /// This behaves like `Limited` for the current operation. If that
+    /// operation succeeds, the following one returns the error instead of
+    /// calling into the underlying operation, without consuming another op.
+    /// This models events like "wrote 4 bytes, then the connection was reset".
+    LimitedThenErr(usize, io::ErrorKind),
+
+    /// Return `Ok(0)` instead of calling into the underlying operation.
+    ///
+    /// For readers, this simulates reaching the end of the stream. For
+    /// writers, it simulates a writer that no longer accepts data. Flushes
+    /// ignore this and call into the underlying operation.
+    Eof,
+
+    /// Limit each buffer of a vectored read independently.
+    ///
+    /// In `read_vectored`, slice `i` is filled with at most `caps[i]` bytes,
+    /// one slice after another, until a slice isn't filled up to its cap.
+    /// Slices beyond the end of `caps` are unlimited, and extra caps are
+    /// ignored. This models drivers that fill scatter buffers with
+    /// per-segment limits.
+    ///
+    /// Other operations, including vectored reads on wrappers that don't
+    /// support this, use a single buffer and limit it to the first cap.
+    PerSlice(Vec<usize>),
+
+    /// Report a write of the whole buffer, but only pass the first `n` bytes
+    /// on to the underlying writer.
+    ///
+    /// The remaining bytes are silently dropped, modeling a lossy transport
+    /// where the caller believes the whole write succeeded. The prefix is
+    /// written in full, so a short write by the underlying writer doesn't
+    /// drop any more data. Readers treat this like `Limited(n)`.
+    DropTail(usize),
+
+    /// Fail operations on buffers larger than the given number of bytes.
+    ///
+    /// A larger buffer makes the operation fail with an
+    /// `io::ErrorKind::InvalidInput` error, which carries a
+    /// [`BufferTooLargeError`](struct.BufferTooLargeError.html) as its inner
+    /// error. This models transports with a maximum transfer size, where the
+    /// caller has to retry with a smaller buffer.
+    ///
+    /// Operations on buffers of at most the given size pass straight through
+    /// to the underlying reader or writer, and don't consume this op. It stays
+    /// in place until it rejects a buffer. Flushes and shutdowns don't pass on
+    /// a buffer, so they go through without consuming it either.
+    BufferTooLarge(usize),
+
+    /// Limit reads to end right after the next occurrence of the given byte,
+    /// e.g. a newline, and writes to end right after its first occurrence in
+    /// the buffer.
+    ///
+    /// This models a transport that delivers one line or record at a time.
+    /// Writers and `InMemoryDuplex` can look for the delimiter in the data
+    /// directly. Other readers can't see the data before reading it, so to
+    /// avoid consuming anything past the delimiter they read from the
+    /// underlying reader one byte at a time, until the delimiter, the end of
+    /// the buffer, the end of file or an error. The underlying reader doesn't
+    /// need to be buffered as a result, but it sees many single-byte reads. An
+    /// error after some bytes have been read ends the read early and is left
+    /// for the next one to run into.
+    ///
+    /// See [`DelimiterOps`](struct.DelimiterOps.html) for a source of these
+    /// ops.
+    Delimiter(u8),
+
+    /// Make a flush report success without flushing the underlying writer.
+    ///
+    /// This models a lying flush, to check that code doesn't rely on flushed
+    /// data having made it to the sink, e.g. for crash consistency. The flush
+    /// returns `Ok(())` without flushing the underlying writer. For
+    /// `PartialWrite`, and `PartialAsyncWrite` without a send buffer, that's
+    /// all it does: no data is discarded, and whatever the underlying writer
+    /// holds back still reaches the sink on a later flush. Only with
+    /// [`PartialAsyncWrite::with_buffer_capacity`] is data lost: the data held
+    /// in the send buffer is discarded, so the underlying writer never receives
+    /// it. Reads and writes treat this op like `Unlimited`.
+    ///
+    /// [`PartialAsyncWrite::with_buffer_capacity`]: struct.PartialAsyncWrite.html#method.with_buffer_capacity
+    FlushDrops,
+}
+
+/// A compact, human-readable representation, intended for logs and snapshots.
+///
+/// `Limited(n)` is displayed as `limit:n`, `Unlimited` as `unlimited`,
+/// `Err(kind)` as `err:kind`, e.g. `err:WouldBlock`, and
+/// `LimitedThenErr(n, kind)` as `limit-then-err:n:kind`, `Eof` as `eof`, and
+/// `PerSlice(caps)` as `per-slice:` followed by the caps separated by commas,
+/// e.g. `per-slice:2,0,4`. `DropTail(n)` is displayed as `drop-tail:n`, and
+/// `BufferTooLarge(max)` as `buffer-too-large:max` and `Delimiter(byte)` as
+/// `delimiter:byte`, with the byte in decimal. `FlushDrops` is displayed as
+/// `flush-drops`.
+///
+/// This form can be parsed back with `str::parse`, or with
+/// `PartialOp::parse_sequence` for several ops at once. That works for every
+/// op except ones carrying an `io::ErrorKind` that is unstable or was
+/// stabilized after Rust 1.59, the minimum supported version, such as
+/// `StorageFull`. Those fail to parse as an unknown error kind.
+impl fmt::Display for PartialOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialOp::Limited(n) => write!(f, "limit:{}", n),
+            PartialOp::Unlimited => f.write_str("unlimited"),
+            PartialOp::Err(kind) => write!(f, "err:{:?}", kind),
+            PartialOp::LimitedThenErr(n, kind) => write!(f, "limit-then-err:{}:{:?}", n, kind),
+            PartialOp::Eof => f.write_str("eof"),
+            PartialOp::PerSlice(caps) => {
+                f.write_str("per-slice:")?;
+                for (i, cap) in caps.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", cap)?;
+                }
+                Ok(())
+            }
+            PartialOp::DropTail(n) => write!(f, "drop-tail:{}", n),
+            PartialOp::BufferTooLarge(max) => write!(f, "buffer-too-large:{}", max),
+            PartialOp::Delimiter(byte) => write!(f, "delimiter:{}", byte),
+            PartialOp::FlushDrops => f.write_str("flush-drops"),
+        }
+    }
 }
 
-#[inline]
-fn make_ops<I>(iter: I) -> Box<dyn Iterator<Item = PartialOp> + Send>
+impl PartialOp {
+    /// Creates a `PartialOp::Limited` op.
+    #[inline]
+    pub const fn limited(n: usize) -> Self {
+        PartialOp::Limited(n)
+    }
+
+    /// Creates a `PartialOp::Unlimited` op.
+    #[inline]
+    pub const fn unlimited() -> Self {
+        PartialOp::Unlimited
+    }
+
+    /// Creates a `PartialOp::Err` op.
+    #[inline]
+    pub const fn err(kind: io::ErrorKind) -> Self {
+        PartialOp::Err(kind)
+    }
+
+    /// Creates a `PartialOp::LimitedThenErr` op.
+    #[inline]
+    pub const fn limited_then_err(n: usize, kind: io::ErrorKind) -> Self {
+        PartialOp::LimitedThenErr(n, kind)
+    }
+
+    /// Creates a `PartialOp::Eof` op.
+    #[inline]
+    pub const fn eof() -> Self {
+        PartialOp::Eof
+    }
+
+    /// Creates a `PartialOp::PerSlice` op.
+    #[inline]
+    pub fn per_slice(caps: Vec<usize>) -> Self {
+        PartialOp::PerSlice(caps)
+    }
+
+    /// Creates a `PartialOp::DropTail` op.
+    #[inline]
+    pub const fn drop_tail(n: usize) -> Self {
+        PartialOp::DropTail(n)
+    }
+
+    /// Creates a `PartialOp::BufferTooLarge` op.
+    #[inline]
+    pub const fn buffer_too_large(max: usize) -> Self {
+        PartialOp::BufferTooLarge(max)
+    }
+
+    /// Creates a `PartialOp::Delimiter` op.
+    #[inline]
+    pub const fn delimiter(byte: u8) -> Self {
+        PartialOp::Delimiter(byte)
+    }
+
+    /// Creates a `PartialOp::FlushDrops` op.
+    #[inline]
+    pub const fn flush_drops() -> Self {
+        PartialOp::FlushDrops
+    }
+
+    /// Creates the op that reproduces `res`, the result of a read or write.
+    ///
+    /// `Ok(0)` becomes `Eof`, any other `Ok(n)` becomes `Limited(n)` and an
+    /// error becomes `Err` with the same kind. This is what the `from_results`
+    /// constructors of the wrappers use to replay a recorded trace.
+    pub fn from_result(res: &io::Result<usize>) -> Self {
+        match res {
+            Ok(0) => PartialOp::Eof,
+            Ok(n) => PartialOp::Limited(*n),
+            Err(err) => PartialOp::Err(err.kind()),
+        }
+    }
+
+    /// Interprets arbitrary bytes, e.g. the input of a fuzz target, as a
+    /// sequence of ops.
+    ///
+    /// Each byte becomes one op: `0x00..=0x1f` is a `WouldBlock` error,
+    /// `0x20..=0x3f` an `Interrupted` error, `0xf0..=0xff` is `Unlimited`, and
+    /// any other byte `b` is `Limited(b - 0x3f)`, so limits are between 1 and
+    /// 176. The same bytes always produce the same ops.
+    ///
+    /// The result always lets an operation make progress: error bytes that
+    /// would make for more than 4 errors in a row are skipped, and if the
+    /// sequence would end with an error, `Unlimited` is appended. Retrying on
+    /// errors therefore never hangs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io;
+    ///
+    /// use partial_io::PartialOp;
+    ///
+    /// assert_eq!(
+    ///     PartialOp::from_fuzz_bytes(&[0x41, 0x00, 0x20, 0xff]),
+    ///     vec![
+    ///         PartialOp::Limited(2),
+    ///         PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///         PartialOp::Err(io::ErrorKind::Interrupted),
+    ///         PartialOp::Unlimited,
+    ///     ],
+    /// );
+    /// ```
+    pub fn from_fuzz_bytes(data: &[u8]) -> Vec<PartialOp> {
+        const MAX_CONSECUTIVE_ERRORS: usize = 4;
+
+        let mut ops = Vec::with_capacity(data.len() + 1);
+        let mut errors = 0;
+        for &byte in data {
+            let op = match byte {
+                0x00..=0x1f => PartialOp::Err(io::ErrorKind::WouldBlock),
+                0x20..=0x3f => PartialOp::Err(io::ErrorKind::Interrupted),
+                0xf0..=0xff => PartialOp::Unlimited,
+                _ => PartialOp::Limited(byte as usize - 0x3f),
+            };
+            if let PartialOp::Err(_) = op {
+                if errors == MAX_CONSECUTIVE_ERRORS {
+                    continue;
+                }
+                errors += 1;
+            } else {
+                errors = 0;
+            }
+            ops.push(op);
+        }
+        if errors > 0 {
+            ops.push(PartialOp::Unlimited);
+        }
+        ops
+    }
+}
+
+impl<'a> From<&'a PartialOp> for PartialOp {
+    #[inline]
+    fn from(op: &'a PartialOp) -> Self {
+        op.clone()
+    }
+}
+
+/// Collects `PartialOp`s, or references to them, into a `Vec`.
+///
+/// This is convenient for turning an iterator of borrowed ops, such as one
+/// derived from a shared table, into something a wrapper can own.
+pub fn collect_ops<I>(iter: I) -> Vec<PartialOp>
 where
-    I: IntoIterator<Item = PartialOp> + 'static,
-    I::IntoIter: Send,
+    I: IntoIterator,
+    I::Item: Into<PartialOp>,
 {
-    Box::new(iter.into_iter().fuse())
+    iter.into_iter().map(Into::into).collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     pub fn assert_send<S: Send>() {}
+
+    #[test]
+    fn test_ops_from_slice() {
+        use std::io::{Cursor, Read};
+
+        const OPS: &[PartialOp] = &[PartialOp::Limited(2), PartialOp::Limited(1)];
+        let mut first = PartialRead::new(Cursor::new(vec![1, 2, 3]), OPS);
+        let mut second = PartialRead::new(Cursor::new(vec![4, 5, 6]), OPS.iter());
+
+        let mut out = vec![0; 256];
+        assert_eq!(first.read(&mut out).unwrap(), 2);
+        assert_eq!(second.read(&mut out).unwrap(), 2);
+        assert_eq!(first.read(&mut out).unwrap(), 1);
+        assert_eq!(second.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_collect_ops() {
+        let ops = [PartialOp::Limited(2), PartialOp::Unlimited];
+        let collected = collect_ops(ops.iter().rev());
+        assert_eq!(collected, vec![PartialOp::Unlimited, PartialOp::Limited(2)]);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(PartialOp::Limited(4).to_string(), "limit:4");
+        assert_eq!(PartialOp::Unlimited.to_string(), "unlimited");
+        assert_eq!(
+            PartialOp::Err(io::ErrorKind::WouldBlock).to_string(),
+            "err:WouldBlock"
+        );
+        assert_eq!(
+            PartialOp::LimitedThenErr(4, io::ErrorKind::ConnectionReset).to_string(),
+            "limit-then-err:4:ConnectionReset"
+        );
+        assert_eq!(PartialOp::Eof.to_string(), "eof");
+        assert_eq!(
+            PartialOp::PerSlice(vec![2, 0, 4]).to_string(),
+            "per-slice:2,0,4"
+        );
+        assert_eq!(PartialOp::DropTail(3).to_string(), "drop-tail:3");
+        assert_eq!(
+            PartialOp::BufferTooLarge(512).to_string(),
+            "buffer-too-large:512"
+        );
+        assert_eq!(PartialOp::Delimiter(b'\n').to_string(), "delimiter:10");
+        assert_eq!(PartialOp::FlushDrops.to_string(), "flush-drops");
+    }
+
+    #[test]
+    fn test_constructors() {
+        assert_eq!(PartialOp::limited(4), PartialOp::Limited(4));
+        assert_eq!(PartialOp::unlimited(), PartialOp::Unlimited);
+        assert_eq!(
+            PartialOp::err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::Interrupted)
+        );
+        assert_eq!(
+            PartialOp::limited_then_err(2, io::ErrorKind::BrokenPipe),
+            PartialOp::LimitedThenErr(2, io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[test]
+    fn test_from_fuzz_bytes() {
+        let inputs: [&[u8]; 5] = [
+            &[],
+            &[0; 100],
+            &[0x3f, 0x40, 0xef, 0xf0],
+            b"\x00\x20\x01\x21\x02\x22 some fuzzer input",
+            &[0x1f, 0x80, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f],
+        ];
+        for data in &inputs {
+            let ops = PartialOp::from_fuzz_bytes(data);
+            assert_eq!(ops, PartialOp::from_fuzz_bytes(data));
+            assert!(ops.len() <= data.len() + 1);
+            // Every run of errors is short and followed by progress.
+            let mut errors = 0;
+            for op in &ops {
+                match op {
+                    PartialOp::Err(_) => errors += 1,
+                    PartialOp::Limited(n) => {
+                        assert!((1..=176).contains(n));
+                        errors = 0;
+                    }
+                    PartialOp::Unlimited => errors = 0,
+                    _ => panic!("unexpected op {}", op),
+                }
+                assert!(errors <= 4);
+            }
+            assert_eq!(errors, 0);
+
+            // Driving a transfer through the schedule finishes.
+            let payload: Vec<u8> = (0..=255).collect();
+            let mut reader = PartialRead::new(io::Cursor::new(payload.clone()), ops.clone());
+            let mut writer = PartialWrite::new(Vec::new(), ops);
+            transfer::<16, _, _>(&mut reader, &mut writer).unwrap();
+            assert_eq!(writer.into_inner(), payload);
+        }
+        assert_eq!(
+            PartialOp::from_fuzz_bytes(&[0x3f, 0x40, 0xef])[1..],
+            [PartialOp::Limited(1), PartialOp::Limited(176)]
+        );
+    }
+
+    #[test]
+    fn test_from_result() {
+        assert_eq!(PartialOp::from_result(&Ok(3)), PartialOp::Limited(3));
+        assert_eq!(PartialOp::from_result(&Ok(0)), PartialOp::Eof);
+        let err = io::Error::new(io::ErrorKind::WouldBlock, "blocked");
+        assert_eq!(
+            PartialOp::from_result(&Err(err)),
+            PartialOp::Err(io::ErrorKind::WouldBlock)
+        );
+    }
 }
@@ -0,0 +1,242 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains the observers that wrappers notify about the ops they
+//! apply.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::PartialOp;
+
+/// An observer that is notified about every `PartialOp` a wrapper applies.
+///
+/// Observers are registered with `add_observer` on any of the wrappers, and
+/// any number of them can be registered at once. They are notified in the
+/// order they were added, after the built-in recording enabled by
+/// `set_recording`.
+///
+/// This is implemented for closures taking the same arguments as `on_op`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+/// use std::sync::{Arc, Mutex};
+///
+/// use partial_io::{PartialOp, PartialRead};
+///
+/// let seen = Arc::new(Mutex::new(Vec::new()));
+/// let seen2 = seen.clone();
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), vec![PartialOp::Limited(2)]);
+/// reader.add_observer(move |op: &PartialOp, bytes: usize| {
+///     seen2.lock().unwrap().push((op.clone(), bytes));
+/// });
+///
+/// let mut out = vec![0; 8];
+/// assert_eq!(reader.read(&mut out).unwrap(), 2);
+/// assert_eq!(&*seen.lock().unwrap(), &[(PartialOp::Limited(2), 2)]);
+/// ```
+pub trait OpObserver: Send {
+    /// Called after `op` was applied, with the number of bytes the operation
+    /// transferred.
+    ///
+    /// Once the ops have run out, `op` is `PartialOp::Unlimited`. Failed
+    /// operations transferred 0 bytes.
+    fn on_op(&mut self, op: &PartialOp, bytes: usize);
+}
+
+impl<F> OpObserver for F
+where
+    F: FnMut(&PartialOp, usize) + Send,
+{
+    #[inline]
+    fn on_op(&mut self, op: &PartialOp, bytes: usize) {
+        self(op, bytes)
+    }
+}
+
+/// An observer that measures the wall-clock time between successive successful
+/// operations.
+///
+/// An operation counts as successful if it transferred at least one byte.
+/// Each one is timestamped with `Instant::now()`, and the time elapsed since
+/// the previous one is logged, so `n` successful operations log `n - 1` gaps.
+/// This is handy to check that a schedule actually imposed the latencies it
+/// was meant to, and to find the ops behind slow operations.
+///
+/// Clones share the same log, so one clone can be added to a wrapper with
+/// `add_observer` while another is kept to read the gaps. Nothing is measured
+/// unless the observer is added.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{PartialOp, PartialRead, TimingObserver};
+///
+/// let timing = TimingObserver::new();
+/// let ops = vec![PartialOp::Limited(1), PartialOp::Limited(1)];
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2]), ops);
+/// reader.add_observer(timing.clone());
+///
+/// let mut out = vec![0; 8];
+/// reader.read(&mut out).unwrap();
+/// assert!(timing.gaps().is_empty());
+/// reader.read(&mut out).unwrap();
+/// assert_eq!(timing.gaps().len(), 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TimingObserver {
+    shared: Arc<Mutex<Timing>>,
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    last: Option<Instant>,
+    gaps: Vec<Duration>,
+}
+
+impl TimingObserver {
+    /// Creates a new observer with an empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the time between each successful operation and the one before
+    /// it, in order.
+    pub fn gaps(&self) -> Vec<Duration> {
+        self.shared.lock().unwrap().gaps.clone()
+    }
+}
+
+impl OpObserver for TimingObserver {
+    fn on_op(&mut self, _op: &PartialOp, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut timing = self.shared.lock().unwrap();
+        if let Some(last) = timing.last.replace(now) {
+            timing.gaps.push(now - last);
+        }
+    }
+}
+
+/// The observer behind `set_recording`.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    pub(crate) ops: Vec<(PartialOp, usize)>,
+}
+
+impl OpObserver for Recorder {
+    #[inline]
+    fn on_op(&mut self, op: &PartialOp, bytes: usize) {
+        self.ops.push((op.clone(), bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{self, Cursor, Read};
+    use std::thread;
+
+    use crate::PartialRead;
+
+    type Log = Arc<Mutex<Vec<(&'static str, PartialOp, usize)>>>;
+
+    /// An observer that logs ops under a name, to check the order.
+    struct NamedObserver {
+        name: &'static str,
+        log: Log,
+    }
+
+    impl OpObserver for NamedObserver {
+        fn on_op(&mut self, op: &PartialOp, bytes: usize) {
+            self.log
+                .lock()
+                .unwrap()
+                .push((self.name, op.clone(), bytes));
+        }
+    }
+
+    #[test]
+    fn test_multiple_observers() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        partial_read.set_recording(true);
+        for &name in &["a", "b"] {
+            partial_read.add_observer(NamedObserver {
+                name,
+                log: log.clone(),
+            });
+        }
+
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        partial_read.read(&mut out).unwrap_err();
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+
+        let interrupted = PartialOp::Err(io::ErrorKind::Interrupted);
+        assert_eq!(
+            &*log.lock().unwrap(),
+            &[
+                ("a", PartialOp::Limited(2), 2),
+                ("b", PartialOp::Limited(2), 2),
+                ("a", interrupted.clone(), 0),
+                ("b", interrupted.clone(), 0),
+                ("a", PartialOp::Unlimited, 1),
+                ("b", PartialOp::Unlimited, 1),
+            ]
+        );
+        // Recording still works alongside the observers.
+        assert_eq!(
+            partial_read.recorded(),
+            &[
+                (PartialOp::Limited(2), 2),
+                (interrupted, 0),
+                (PartialOp::Unlimited, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timing_observer() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(1),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        let timing = TimingObserver::new();
+        partial_read.add_observer(timing.clone());
+
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        // There is no op that delays a read, so stall between reads instead.
+        thread::sleep(Duration::from_millis(50));
+        partial_read.read(&mut out).unwrap_err();
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        // Reading EOF doesn't count as a successful operation.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 0);
+
+        let gaps = timing.gaps();
+        assert_eq!(gaps.len(), 2);
+        assert!(gaps[0] >= Duration::from_millis(50), "{:?}", gaps);
+        assert!(gaps[1] < gaps[0], "{:?}", gaps);
+    }
+}
@@ -0,0 +1,988 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains the sequence of `PartialOp`s shared by all the
+//! wrappers.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::iter;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::observer::{OpObserver, Recorder};
+use crate::PartialOp;
+
+thread_local! {
+    static THREAD_LOCAL_OPS: RefCell<Box<dyn Iterator<Item = PartialOp>>> =
+        RefCell::new(Box::new(iter::empty()));
+}
+
+/// Sets the `PartialOp`s that wrappers created with
+/// `PartialRead::from_thread_local` draw from on the current thread.
+///
+/// Every thread has its own sequence, which starts out empty. It stays in place
+/// until it is replaced by calling this again on the same thread, and goes away
+/// when the thread exits. All of the thread-local wrappers used on a thread
+/// share its sequence: an op drawn by one of them isn't seen by the others.
+/// Passing an empty sequence clears it.
+///
+/// The iterator doesn't have to be `Send`, since it never leaves the thread.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+/// use std::thread;
+///
+/// use partial_io::{PartialOp, PartialRead};
+///
+/// let handles: Vec<_> = (1..=2)
+///     .map(|n| {
+///         thread::spawn(move || {
+///             partial_io::set_thread_local_ops(vec![PartialOp::Limited(n)]);
+///             let mut reader = PartialRead::from_thread_local(Cursor::new(vec![0; 8]));
+///             reader.read(&mut [0; 8]).unwrap()
+///         })
+///     })
+///     .collect();
+/// let sizes: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+/// assert_eq!(sizes, vec![1, 2]);
+/// ```
+pub fn set_thread_local_ops<I>(iter: I)
+where
+    I: IntoIterator + 'static,
+    I::Item: Into<PartialOp>,
+{
+    let iter = Box::new(iter.into_iter().map(Into::into));
+    THREAD_LOCAL_OPS.with(|ops| *ops.borrow_mut() = iter);
+}
+
+/// Draws ops from `THREAD_LOCAL_OPS` of whichever thread calls `next`.
+struct ThreadLocalOps;
+
+impl Iterator for ThreadLocalOps {
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<PartialOp> {
+        // During thread teardown the sequence may already be gone, in which
+        // case the ops have run out.
+        THREAD_LOCAL_OPS
+            .try_with(|ops| ops.borrow_mut().next())
+            .ok()
+            .flatten()
+    }
+}
+
+/// The most `WouldBlock` ops `Ops::skip_blocks` skips in one call.
+#[cfg(feature = "tokio")]
+const MAX_SKIPPED_BLOCKS: usize = 1024;
+
+/// The `PartialOp`s for a wrapper, along with any state tracked about them.
+pub(crate) struct Ops {
+    iter: Box<dyn Iterator<Item = PartialOp> + Send>,
+    expect_all_consumed: bool,
+    recorder: Option<Recorder>,
+    observers: Vec<Box<dyn OpObserver>>,
+    // An op to return before drawing from `iter` again.
+    pending: Option<PartialOp>,
+    // The error set up by a `LimitedThenErr` op, to return before any other
+    // op. It is part of that op, so it doesn't count as another one.
+    tail: Option<PartialOp>,
+    // Set while the op drawn with `next` is `tail`.
+    applying_tail: bool,
+    consumed: usize,
+    bytes: u64,
+    stats: PartialIoStats,
+    fail_flag: Option<(Arc<AtomicBool>, io::ErrorKind)>,
+    poison_on_error: bool,
+    // Set once a fatal error has been injected, if `poison_on_error` is set.
+    poisoned: bool,
+    // Set while an op drawn with `next` hasn't been completed yet.
+    in_progress: bool,
+    forbid_would_block: bool,
+    #[cfg(feature = "tokio")]
+    max_consecutive_blocks: Option<usize>,
+    // The number of `WouldBlock` ops applied in a row.
+    consecutive_blocks: usize,
+    // The index of the recorded entry for the last op applied, which a strict
+    // limit carried over from it adds its bytes to.
+    carry_entry: Option<usize>,
+}
+
+/// Where a wrapper got the op it applies from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum OpSource {
+    /// The next op drawn from the script with `next`.
+    Script,
+    /// An op the wrapper applies in place of the script, such as the op set
+    /// with `PartialRead::set_post_seek_op`.
+    Injected,
+    /// The rest of a strict `Limited` op, carried over from an earlier read.
+    Carried,
+}
+
+impl Ops {
+    #[inline]
+    pub(crate) fn new<I>(iter: I) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Ops {
+            iter: make_iter(iter),
+            expect_all_consumed: false,
+            recorder: None,
+            observers: Vec::new(),
+            pending: None,
+            tail: None,
+            applying_tail: false,
+            consumed: 0,
+            bytes: 0,
+            stats: PartialIoStats::default(),
+            fail_flag: None,
+            poison_on_error: false,
+            poisoned: false,
+            in_progress: false,
+            forbid_would_block: false,
+            #[cfg(feature = "tokio")]
+            max_consecutive_blocks: None,
+            consecutive_blocks: 0,
+            carry_entry: None,
+        }
+    }
+
+    /// Creates a new `Ops` that draws from the thread-local ops of the thread
+    /// using it.
+    ///
+    /// Unlike other iterators, this isn't fused, since the thread-local ops can
+    /// be replaced after running out.
+    pub(crate) fn thread_local() -> Self {
+        let mut ops = Self::new(iter::empty::<PartialOp>());
+        ops.iter = Box::new(ThreadLocalOps);
+        ops
+    }
+
+    /// Creates a new `Ops`, failing if `iter` is empty.
+    pub(crate) fn new_nonempty<I>(iter: I) -> Result<Self, EmptyOps>
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        let mut ops = Self::new(iter);
+        match ops.peek() {
+            Some(_) => Ok(ops),
+            None => Err(EmptyOps),
+        }
+    }
+
+    /// Replaces the remaining ops with the ones from `iter`.
+    ///
+    /// An error set up by a `LimitedThenErr` op that was already applied
+    /// belongs to that op rather than to the remaining ones, so it is kept and
+    /// still returned first.
+    #[inline]
+    pub(crate) fn set<I>(&mut self, iter: I)
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.iter = make_iter(iter);
+        self.pending = None;
+    }
+
+    /// Resets the counts of ops consumed and bytes transferred, along with the
+    /// stats.
+    pub(crate) fn reset_counts(&mut self) {
+        self.consumed = 0;
+        self.bytes = 0;
+        self.stats = PartialIoStats::default();
+    }
+
+    #[inline]
+    pub(crate) fn expect_all_consumed(&mut self) {
+        self.expect_all_consumed = true;
+    }
+
+    pub(crate) fn set_recording(&mut self, record: bool) {
+        if !record {
+            self.recorder = None;
+        } else if self.recorder.is_none() {
+            self.recorder = Some(Recorder::default());
+            self.carry_entry = None;
+        }
+    }
+
+    pub(crate) fn add_observer(&mut self, observer: Box<dyn OpObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Updates the state after `op` was applied. `bytes` is the number of
+    /// bytes transferred, or `None` if the operation failed.
+    ///
+    /// `None` for `op` means that the ops have run out, which behaves like
+    /// `PartialOp::Unlimited`.
+    #[inline]
+    pub(crate) fn complete(&mut self, op: Option<PartialOp>, bytes: Option<usize>) {
+        let scripted = !mem::take(&mut self.applying_tail);
+        self.apply(op, bytes, scripted);
+    }
+
+    /// Updates the state after `op` was applied like `complete`, counting it
+    /// towards the ops consumed and the stats only if `scripted` is set.
+    fn apply(&mut self, op: Option<PartialOp>, bytes: Option<usize>, scripted: bool) {
+        self.in_progress = false;
+        if self.forbid_would_block {
+            if let Some(PartialOp::Err(io::ErrorKind::WouldBlock)) = op {
+                panic!(
+                    "partial-io: a WouldBlock op was applied by a sync wrapper, which \
+                     nothing will retry; use PartialAsyncRead or PartialAsyncWrite to \
+                     simulate non-blocking I/O"
+                );
+            }
+        }
+        match (&op, bytes) {
+            (Some(PartialOp::Err(io::ErrorKind::WouldBlock)), None) => self.consecutive_blocks += 1,
+            _ => self.consecutive_blocks = 0,
+        }
+        if let (Some(PartialOp::LimitedThenErr(_, err)), Some(_)) = (&op, bytes) {
+            self.tail = Some(PartialOp::Err(*err));
+        }
+        if scripted && op.is_some() {
+            self.consumed += 1;
+        }
+        if let (true, Some(PartialOp::Err(kind)), None) = (self.poison_on_error, &op, bytes) {
+            self.poisoned |= !is_retryable(*kind);
+        }
+        self.bytes += bytes.unwrap_or(0) as u64;
+        if scripted {
+            self.stats.count(op.as_ref());
+        }
+        self.carry_entry = self.recorder.as_ref().map(|recorder| recorder.ops.len());
+        if self.recorder.is_none() && self.observers.is_empty() {
+            return;
+        }
+        let op = op.unwrap_or(PartialOp::Unlimited);
+        let bytes = bytes.unwrap_or(0);
+        let recorder = self
+            .recorder
+            .as_mut()
+            .map(|recorder| recorder as &mut dyn OpObserver);
+        let observers = self.observers.iter_mut().map(|observer| &mut **observer);
+        for observer in recorder.into_iter().chain(observers) {
+            observer.on_op(&op, bytes);
+        }
+    }
+
+    /// Updates the state after applying an op from `source`, like `complete`.
+    #[inline]
+    pub(crate) fn complete_from(
+        &mut self,
+        source: OpSource,
+        op: Option<PartialOp>,
+        bytes: Option<usize>,
+    ) {
+        match source {
+            OpSource::Script => self.complete(op, bytes),
+            OpSource::Injected => self.apply(op, bytes, false),
+            OpSource::Carried => self.complete_carried(bytes),
+        }
+    }
+
+    /// Updates the state after a read continued a strict `Limited` op carried
+    /// over from an earlier read.
+    ///
+    /// The continuation is part of the op it was carried over from, so it
+    /// doesn't count as another op consumed or in the stats. Its bytes are
+    /// added to that op's recorded entry, and observers aren't notified again.
+    fn complete_carried(&mut self, bytes: Option<usize>) {
+        let bytes = bytes.unwrap_or(0);
+        self.consecutive_blocks = 0;
+        self.bytes += bytes as u64;
+        let entry = match (&mut self.recorder, self.carry_entry) {
+            (Some(recorder), Some(index)) => recorder.ops.get_mut(index),
+            _ => None,
+        };
+        if let Some((_, recorded)) = entry {
+            *recorded += bytes;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn stats(&self) -> PartialIoStats {
+        self.stats
+    }
+
+    pub(crate) fn recorded(&self) -> &[(PartialOp, usize)] {
+        self.recorder
+            .as_ref()
+            .map_or(&[], |recorder| &recorder.ops[..])
+    }
+
+    pub(crate) fn drain_recorded(&mut self) -> impl Iterator<Item = (PartialOp, usize)> + '_ {
+        // The `Drain` is created up front, so that the log is emptied even if
+        // the iterator is never advanced.
+        self.carry_entry = None;
+        self.recorder
+            .as_mut()
+            .map(|recorder| recorder.ops.drain(..))
+            .into_iter()
+            .flatten()
+    }
+
+    pub(crate) fn dump_recorded(&self, w: &mut dyn Write) -> io::Result<()> {
+        for (op, bytes) in self.recorded() {
+            writeln!(w, "{} {}", op, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Consumes up to `n` ops without applying them, returning how many were
+    /// consumed.
+    pub(crate) fn skip_ops(&mut self, n: usize) -> usize {
+        let skipped = iter::from_fn(|| self.draw()).take(n).count();
+        self.consumed += skipped;
+        skipped
+    }
+
+    #[inline]
+    pub(crate) fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Returns the next op without consuming it.
+    pub(crate) fn peek(&mut self) -> Option<&PartialOp> {
+        if self.tail.is_some() {
+            return self.tail.as_ref();
+        }
+        if self.pending.is_none() {
+            self.pending = self.iter.next();
+        }
+        self.pending.as_ref()
+    }
+
+    /// Makes every operation fail with `kind` while `flag` is set.
+    #[inline]
+    pub(crate) fn set_fail_flag(&mut self, flag: Arc<AtomicBool>, kind: io::ErrorKind) {
+        self.fail_flag = Some((flag, kind));
+    }
+
+    /// Returns an error if the flag set with `set_fail_flag` is set.
+    #[inline]
+    pub(crate) fn check_fail_flag(&self, operation: Operation) -> io::Result<()> {
+        match &self.fail_flag {
+            Some((flag, kind)) if flag.load(Ordering::Relaxed) => Err(io::Error::new(
+                *kind,
+                format!(
+                    "fail flag set during {}, generated by partial-io",
+                    operation
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[inline]
+    pub(crate) fn set_max_consecutive_blocks(&mut self, max: Option<usize>) {
+        self.max_consecutive_blocks = max;
+    }
+
+    /// Skips the rest of a run of `WouldBlock` ops once `max_consecutive_blocks`
+    /// of them have been applied in a row.
+    ///
+    /// At most `MAX_SKIPPED_BLOCKS` ops are skipped per call, so that a script
+    /// of nothing but `WouldBlock` ops fails instead of hanging.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn skip_blocks(&mut self, operation: Operation) -> io::Result<()> {
+        match self.max_consecutive_blocks {
+            Some(max) if self.consecutive_blocks >= max => {}
+            _ => return Ok(()),
+        }
+        match self.tail {
+            // This is part of the `WouldBlock` run, but not an op of its own.
+            Some(PartialOp::Err(io::ErrorKind::WouldBlock)) => self.tail = None,
+            Some(_) => return Ok(()),
+            None => {}
+        }
+        for _ in 0..MAX_SKIPPED_BLOCKS {
+            match self.peek() {
+                Some(PartialOp::Err(io::ErrorKind::WouldBlock)) => {
+                    self.pending = None;
+                    self.consumed += 1;
+                }
+                _ => return Ok(()),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "max_consecutive_blocks exhausted the script during {}, generated by partial-io",
+                operation
+            ),
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn forbid_would_block(&mut self, forbid: bool) {
+        self.forbid_would_block = forbid;
+    }
+
+    #[inline]
+    pub(crate) fn set_poison_on_error(&mut self, poison: bool) {
+        self.poison_on_error = poison;
+        if !poison {
+            self.poisoned = false;
+        }
+    }
+
+    /// Returns a `NotConnected` error if an earlier fatal error poisoned the
+    /// wrapper.
+    #[inline]
+    pub(crate) fn check_poisoned(&self, operation: Operation) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!(
+                    "{} after an earlier injected error, generated by partial-io",
+                    operation
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the next op is a `PartialOp::BufferTooLarge` that a
+    /// `len`-byte buffer fits within.
+    ///
+    /// Such operations are forwarded without consuming the op.
+    #[inline]
+    pub(crate) fn fits_buffer_limit(&mut self, len: usize) -> bool {
+        matches!(self.peek(), Some(PartialOp::BufferTooLarge(max)) if len <= *max)
+    }
+
+    /// Returns the number of ops left, if it is known exactly.
+    ///
+    /// This is based on `size_hint`, so unbounded or otherwise unknowable
+    /// sources return `None`. An op that was drawn into `pending` but not
+    /// applied yet counts as remaining, as does an error set up by a
+    /// `LimitedThenErr` op.
+    pub(crate) fn known_remaining(&self) -> Option<usize> {
+        match Iterator::size_hint(self) {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        }
+    }
+
+    /// Returns a one-line summary of the state, printed if the thread panics
+    /// while instrumentation is enabled.
+    fn summary(&self) -> String {
+        let remaining = match self.known_remaining() {
+            Some(remaining) => remaining.to_string(),
+            None => "unknown".to_string(),
+        };
+        format!(
+            "partial-io: state at panic: {} op(s) consumed, {} byte(s) transferred, {} op(s) remaining",
+            self.consumed, self.bytes, remaining
+        )
+    }
+}
+
+impl Ops {
+    /// Takes the next op from the script, without checking or affecting
+    /// whether one is in progress.
+    ///
+    /// This leaves alone any error set up by a `LimitedThenErr` op, which
+    /// isn't part of the script.
+    #[inline]
+    fn draw(&mut self) -> Option<PartialOp> {
+        match self.pending.take() {
+            Some(op) => Some(op),
+            None => self.iter.next(),
+        }
+    }
+}
+
+/// Drawing an op starts applying it, which lasts until `complete` is called.
+///
+/// In debug builds, drawing another op before then panics. Wrappers always
+/// complete an op before returning, so this only happens if the wrapper is
+/// entered again from within an operation, e.g. through an aliased pointer, or
+/// is used again after a panic in the middle of one.
+impl Iterator for Ops {
+    type Item = PartialOp;
+
+    #[inline]
+    fn next(&mut self) -> Option<PartialOp> {
+        debug_assert!(
+            !self.in_progress,
+            "partial-io: op drawn while the previous one is still in progress \
+             (re-entrant use, or use after a panic during an operation)"
+        );
+        self.in_progress = true;
+        if let Some(op) = self.tail.take() {
+            self.applying_tail = true;
+            return Some(op);
+        }
+        self.draw()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.pending.is_some() as usize + self.tail.is_some() as usize;
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_add(pending),
+            upper.and_then(|upper| upper.checked_add(pending)),
+        )
+    }
+}
+
+impl Drop for Ops {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if self.expect_all_consumed || self.recorder.is_some() {
+                eprintln!("{}", self.summary());
+            }
+            return;
+        }
+        if !self.expect_all_consumed {
+            return;
+        }
+        match self.known_remaining() {
+            Some(0) | None => {}
+            Some(remaining) => panic!(
+                "partial-io: {} scripted op(s) were never consumed",
+                remaining
+            ),
+        }
+    }
+}
+
+/// Counts of the `PartialOp`s a wrapper applied, by kind.
+///
+/// This is cheaper than recording every op, and is handy for assertions like
+/// "the retry loop ran into at least 5 `WouldBlock` errors". Operations that
+/// bypass the ops, e.g. because of `set_limit_only_above`, and ops skipped
+/// with `skip_ops` aren't counted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PartialIoStats {
+    /// The number of `Err(WouldBlock)` ops applied.
+    pub would_blocks: usize,
+    /// The number of `Err(Interrupted)` ops applied.
+    pub interrupted: usize,
+    /// The number of other ops that failed the operation, including
+    /// `BufferTooLarge` ops that rejected a buffer.
+    pub other_errors: usize,
+    /// The number of ops that limited the operation: `Limited`,
+    /// `LimitedThenErr`, `PerSlice`, `DropTail` and `Delimiter`.
+    pub limited: usize,
+    /// The number of operations that weren't limited, including ones after the
+    /// ops ran out and ones that applied a `FlushDrops` op.
+    pub unlimited: usize,
+    /// The number of `Eof` ops applied.
+    pub eofs: usize,
+}
+
+impl PartialIoStats {
+    fn count(&mut self, op: Option<&PartialOp>) {
+        let counter = match op {
+            Some(PartialOp::Err(io::ErrorKind::WouldBlock)) => &mut self.would_blocks,
+            Some(PartialOp::Err(io::ErrorKind::Interrupted)) => &mut self.interrupted,
+            Some(PartialOp::Err(_)) | Some(PartialOp::BufferTooLarge(_)) => &mut self.other_errors,
+            Some(PartialOp::Limited(_))
+            | Some(PartialOp::LimitedThenErr(..))
+            | Some(PartialOp::PerSlice(_))
+            | Some(PartialOp::DropTail(_))
+            | Some(PartialOp::Delimiter(_)) => &mut self.limited,
+            // `FlushDrops` doesn't limit writes, and fakes a successful flush.
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => &mut self.unlimited,
+            Some(PartialOp::Eof) => &mut self.eofs,
+        };
+        *counter += 1;
+    }
+}
+
+/// The error returned by the `new_nonempty` constructors when no `PartialOp`s
+/// were provided.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EmptyOps;
+
+impl fmt::Display for EmptyOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no partial-io ops were provided")
+    }
+}
+
+impl error::Error for EmptyOps {}
+
+/// The kind of call an error generated by this crate was injected into.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Operation {
+    /// A `read` call.
+    Read,
+    /// A `write` call.
+    Write,
+    /// A `flush` call.
+    Flush,
+    /// An `AsyncWrite::shutdown` call.
+    Shutdown,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Flush => "flush",
+            Operation::Shutdown => "shutdown",
+        })
+    }
+}
+
+/// The inner error of the errors generated by `PartialOp::Err` and
+/// `PartialOp::LimitedThenErr`.
+///
+/// This records which kind of call the error was injected into, which helps
+/// to find out where an error that went through several layers came from.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Write};
+///
+/// use partial_io::{Operation, PartialIoError, PartialOp, PartialWrite};
+///
+/// let ops = vec![PartialOp::Unlimited, PartialOp::Err(io::ErrorKind::BrokenPipe)];
+/// let mut writer = PartialWrite::new(Vec::new(), ops);
+/// writer.write_all(b"hello").unwrap();
+///
+/// let err = writer.flush().unwrap_err();
+/// assert_eq!(err.to_string(), "error during flush, generated by partial-io");
+/// let injected = err
+///     .get_ref()
+///     .and_then(|err| err.downcast_ref::<PartialIoError>())
+///     .unwrap();
+/// assert_eq!(injected.operation(), Operation::Flush);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PartialIoError {
+    operation: Operation,
+}
+
+impl PartialIoError {
+    /// Returns the kind of call the error was injected into.
+    #[inline]
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+}
+
+impl fmt::Display for PartialIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "error during {}, generated by partial-io",
+            self.operation
+        )
+    }
+}
+
+impl error::Error for PartialIoError {}
+
+/// Returns the error for a `PartialOp::Err(kind)` op applied to `operation`.
+pub(crate) fn injected_error(kind: io::ErrorKind, operation: Operation) -> io::Error {
+    io::Error::new(kind, PartialIoError { operation })
+}
+
+/// The inner error of the `io::ErrorKind::InvalidInput` errors generated by
+/// `PartialOp::BufferTooLarge`.
+///
+/// This can be used to tell these errors apart from other invalid input
+/// errors, and to find out how large a buffer is allowed.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{BufferTooLargeError, PartialOp, PartialRead};
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), vec![PartialOp::BufferTooLarge(2)]);
+/// let err = reader.read(&mut [0; 8]).unwrap_err();
+/// let too_large = err
+///     .get_ref()
+///     .and_then(|err| err.downcast_ref::<BufferTooLargeError>())
+///     .unwrap();
+/// assert_eq!(too_large.max(), 2);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BufferTooLargeError {
+    max: usize,
+}
+
+impl BufferTooLargeError {
+    /// Returns the largest buffer size that is allowed.
+    #[inline]
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+impl fmt::Display for BufferTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "buffer larger than {} byte(s), generated by partial-io",
+            self.max
+        )
+    }
+}
+
+impl error::Error for BufferTooLargeError {}
+
+/// Returns the error for a buffer rejected by `PartialOp::BufferTooLarge(max)`.
+pub(crate) fn buffer_too_large(max: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, BufferTooLargeError { max })
+}
+
+/// Returns the maximum number of bytes `op` lets an operation transfer, or
+/// `None` if it fails the operation.
+///
+/// `None` for `op` means that the ops have run out, which is unlimited.
+pub(crate) fn op_limit(op: Option<&PartialOp>) -> Option<usize> {
+    match op {
+        Some(PartialOp::Limited(n))
+        | Some(PartialOp::LimitedThenErr(n, _))
+        | Some(PartialOp::DropTail(n)) => Some(*n),
+        Some(PartialOp::PerSlice(caps)) => Some(first_cap(caps, usize::MAX)),
+        Some(PartialOp::Eof) => Some(0),
+        Some(PartialOp::BufferTooLarge(max)) => Some(*max),
+        // This depends on the data.
+        Some(PartialOp::Delimiter(_)) => Some(usize::MAX),
+        Some(PartialOp::Err(_)) => None,
+        Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => Some(usize::MAX),
+    }
+}
+
+/// Returns whether an injected error of this kind leaves the wrapper usable.
+#[inline]
+fn is_retryable(kind: io::ErrorKind) -> bool {
+    matches!(kind, io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+}
+
+/// Returns the length a `PartialOp::PerSlice` op limits a single `len`-byte
+/// buffer to.
+#[inline]
+pub(crate) fn first_cap(caps: &[usize], len: usize) -> usize {
+    caps.first().map_or(len, |&cap| cmp::min(cap, len))
+}
+
+/// Returns the length of the prefix of `buf` up to and including the first
+/// occurrence of `delimiter`, or the length of `buf` if there is none.
+#[inline]
+pub(crate) fn delimited_len(buf: &[u8], delimiter: u8) -> usize {
+    buf.iter()
+        .position(|&byte| byte == delimiter)
+        .map_or(buf.len(), |pos| pos + 1)
+}
+
+#[inline]
+fn make_iter<I>(iter: I) -> Box<dyn Iterator<Item = PartialOp> + Send>
+where
+    I: IntoIterator + 'static,
+    I::Item: Into<PartialOp>,
+    I::IntoIter: Send,
+{
+    // Use fuse here so that we don't keep calling the inner iterator once it's
+    // returned None.
+    Box::new(iter.into_iter().map(Into::into).fuse())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+    use std::iter;
+    use std::panic;
+
+    use super::{Operation, Ops, PartialIoError};
+    use crate::{PartialOp, PartialRead, PartialWrite};
+
+    fn operation_of(err: &io::Error) -> Operation {
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<PartialIoError>())
+            .unwrap()
+            .operation()
+    }
+
+    #[test]
+    fn test_limited_then_err_counts_one_op() {
+        let ops = vec![PartialOp::LimitedThenErr(3, io::ErrorKind::Interrupted)];
+        let mut partial_read = PartialRead::new(Cursor::new((0..8).collect::<Vec<u8>>()), ops);
+        partial_read.set_recording(true);
+
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        // The error is part of the `LimitedThenErr` op.
+        assert_eq!(partial_read.ops_consumed(), 1);
+        let stats = partial_read.stats();
+        assert_eq!((stats.limited, stats.interrupted), (1, 0));
+        // It is still recorded, since it was applied to an operation.
+        assert_eq!(
+            partial_read.recorded(),
+            &[
+                (PartialOp::LimitedThenErr(3, io::ErrorKind::Interrupted), 3),
+                (PartialOp::Err(io::ErrorKind::Interrupted), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_ops_keeps_pending_error() {
+        let ops = vec![PartialOp::LimitedThenErr(3, io::ErrorKind::BrokenPipe)];
+        let mut partial_read = PartialRead::new(Cursor::new((0..8).collect::<Vec<u8>>()), ops);
+
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        partial_read.set_ops(vec![PartialOp::Limited(1)]);
+        // The error set up by the old ops comes before the new ones.
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(partial_read.ops_consumed(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "partial-io: 1 scripted op(s) were never consumed")]
+    fn test_expect_all_consumed_panics() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        partial_read.expect_all_consumed();
+
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "partial-io: 1 scripted op(s) were never consumed")]
+    fn test_expect_all_consumed_peeked_nonempty() {
+        // `new_nonempty` draws the first op up front to check for it.
+        let mut partial_read =
+            PartialRead::new_nonempty(Cursor::new(vec![1, 2, 3]), vec![PartialOp::Limited(1)])
+                .unwrap();
+        partial_read.expect_all_consumed();
+    }
+
+    #[test]
+    #[should_panic(expected = "partial-io: 1 scripted op(s) were never consumed")]
+    fn test_expect_all_consumed_peeked_buffer_too_large() {
+        let ops = vec![PartialOp::BufferTooLarge(8)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        partial_read.expect_all_consumed();
+
+        // The read fits within the limit, so the op stays in place.
+        let mut out = vec![0; 4];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.ops_consumed(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "partial-io: 1 scripted op(s) were never consumed")]
+    fn test_expect_all_consumed_pending_error() {
+        let ops = vec![PartialOp::LimitedThenErr(1, io::ErrorKind::Other)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        partial_read.expect_all_consumed();
+
+        // The error this sets up is never surfaced.
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_expect_all_consumed() {
+        let ops = vec![PartialOp::Limited(1), PartialOp::Limited(1)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        partial_read.expect_all_consumed();
+
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_expect_all_consumed_unbounded() {
+        // Unbounded sources are exempt from the check.
+        let mut partial_read = PartialRead::new(
+            Cursor::new(vec![1, 2, 3]),
+            iter::repeat(PartialOp::Limited(1)),
+        );
+        partial_read.expect_all_consumed();
+
+        let mut out = vec![0; 256];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut ops = Ops::new(vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ]);
+        let op = ops.next();
+        ops.complete(op, Some(2));
+        let op = ops.next();
+        ops.complete(op, None);
+        assert_eq!(
+            ops.summary(),
+            "partial-io: state at panic: 2 op(s) consumed, 2 byte(s) transferred, 1 op(s) remaining"
+        );
+    }
+
+    #[test]
+    fn test_no_double_panic() {
+        let res = panic::catch_unwind(|| {
+            let mut partial_read =
+                PartialRead::new(Cursor::new(vec![1, 2, 3]), vec![PartialOp::Limited(1)]);
+            partial_read.expect_all_consumed();
+            panic!("test failure");
+        });
+        // The unconsumed op doesn't cause a second panic while unwinding.
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_injected_error_operation() {
+        let err_op = PartialOp::Err(io::ErrorKind::Other);
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1]), vec![err_op.clone()]);
+        let err = partial_read.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(operation_of(&err), Operation::Read);
+        assert!(err.to_string().contains("during read"));
+
+        let mut partial_write = PartialWrite::new(Vec::new(), vec![err_op.clone(), err_op]);
+        let err = partial_write.write(b"x").unwrap_err();
+        assert_eq!(operation_of(&err), Operation::Write);
+        assert!(err.to_string().contains("during write"));
+        let err = partial_write.flush().unwrap_err();
+        assert_eq!(operation_of(&err), Operation::Flush);
+        assert!(err.to_string().contains("during flush"));
+    }
+}
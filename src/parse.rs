@@ -0,0 +1,298 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module parses `PartialOp`s from the form their `Display` impl
+//! produces.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::PartialOp;
+
+/// The `ErrorKind`s that can be named in an op, matched case-insensitively
+/// against their `Debug` form.
+///
+/// This lists every kind that is stable in the minimum supported Rust version,
+/// 1.59. Kinds stabilized later, such as `StorageFull` or `HostUnreachable`,
+/// can't be named without raising it, so ops with them don't parse. Unstable
+/// kinds can't be named outside of std.
+const ERROR_KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::NotConnected,
+    io::ErrorKind::AddrInUse,
+    io::ErrorKind::AddrNotAvailable,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::WriteZero,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::OutOfMemory,
+    io::ErrorKind::Other,
+];
+
+/// An error from parsing `PartialOp`s with `PartialOp::parse_sequence` or
+/// `str::parse`.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::PartialOp;
+///
+/// let err = PartialOp::parse_sequence("limit:4 limit:x").unwrap_err();
+/// assert_eq!(err.index(), 1);
+/// assert_eq!(
+///     err.to_string(),
+///     "invalid op `limit:x` at index 1: invalid number `x`",
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseOpError {
+    token: String,
+    index: usize,
+    reason: String,
+}
+
+impl ParseOpError {
+    /// Returns the text of the op that failed to parse.
+    #[inline]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns the index of the op that failed to parse within the sequence.
+    ///
+    /// This is always 0 for a single op parsed with `str::parse`.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for ParseOpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid op `{}` at index {}: {}",
+            self.token, self.index, self.reason
+        )
+    }
+}
+
+impl error::Error for ParseOpError {}
+
+impl PartialOp {
+    /// Parses a sequence of ops separated by whitespace.
+    ///
+    /// Each op is in the form its `Display` impl produces, e.g. `limit:4` or
+    /// `err:BrokenPipe`, so the output of `Display` can be parsed back, as long
+    /// as any error kind in it was stable in Rust 1.59. Newer kinds such as
+    /// `StorageFull` fail to parse with an unknown error kind error. Error
+    /// kind names are matched case-insensitively, and a bare error kind name
+    /// such as `wouldblock` is short for `err:wouldblock`. This makes it
+    /// possible to keep scripts of ops in text fixtures.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io;
+    ///
+    /// use partial_io::PartialOp;
+    ///
+    /// let ops = PartialOp::parse_sequence("limit:4 wouldblock limit:2 err:BrokenPipe unlimited").unwrap();
+    /// assert_eq!(
+    ///     ops,
+    ///     vec![
+    ///         PartialOp::Limited(4),
+    ///         PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///         PartialOp::Limited(2),
+    ///         PartialOp::Err(io::ErrorKind::BrokenPipe),
+    ///         PartialOp::Unlimited,
+    ///     ],
+    /// );
+    /// ```
+    pub fn parse_sequence(s: &str) -> Result<Vec<PartialOp>, ParseOpError> {
+        s.split_whitespace()
+            .enumerate()
+            .map(|(index, token)| {
+                parse_op(token).map_err(|reason| ParseOpError {
+                    token: token.to_string(),
+                    index,
+                    reason,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parses a single op, in the same form as `PartialOp::parse_sequence`.
+impl FromStr for PartialOp {
+    type Err = ParseOpError;
+
+    fn from_str(s: &str) -> Result<Self, ParseOpError> {
+        parse_op(s).map_err(|reason| ParseOpError {
+            token: s.to_string(),
+            index: 0,
+            reason,
+        })
+    }
+}
+
+fn parse_op(token: &str) -> Result<PartialOp, String> {
+    let (name, arg) = match token.find(':') {
+        Some(pos) => (&token[..pos], Some(&token[pos + 1..])),
+        None => (token, None),
+    };
+    let op = match (name, arg) {
+        ("limit", Some(arg)) => PartialOp::Limited(parse_number(arg)?),
+        ("unlimited", None) => PartialOp::Unlimited,
+        ("err", Some(arg)) => PartialOp::Err(parse_kind(arg)?),
+        ("limit-then-err", Some(arg)) => {
+            let (n, kind) = match arg.find(':') {
+                Some(pos) => (&arg[..pos], &arg[pos + 1..]),
+                None => return Err("expected `limit-then-err:n:kind`".to_string()),
+            };
+            PartialOp::LimitedThenErr(parse_number(n)?, parse_kind(kind)?)
+        }
+        ("eof", None) => PartialOp::Eof,
+        ("flush-drops", None) => PartialOp::FlushDrops,
+        // `PerSlice(vec![])` is displayed as `per-slice:`.
+        ("per-slice", Some("")) => PartialOp::PerSlice(Vec::new()),
+        ("per-slice", Some(arg)) => {
+            let caps = arg.split(',').map(parse_number).collect::<Result<_, _>>()?;
+            PartialOp::PerSlice(caps)
+        }
+        ("drop-tail", Some(arg)) => PartialOp::DropTail(parse_number(arg)?),
+        ("buffer-too-large", Some(arg)) => PartialOp::BufferTooLarge(parse_number(arg)?),
+        ("delimiter", Some(arg)) => {
+            PartialOp::Delimiter(arg.parse().map_err(|_| format!("invalid byte `{}`", arg))?)
+        }
+        ("limit", None)
+        | ("err", None)
+        | ("limit-then-err", None)
+        | ("per-slice", None)
+        | ("drop-tail", None)
+        | ("buffer-too-large", None)
+        | ("delimiter", None) => return Err(format!("missing argument to `{}`", name)),
+        ("unlimited", Some(_)) | ("eof", Some(_)) | ("flush-drops", Some(_)) => {
+            return Err(format!("`{}` takes no argument", name))
+        }
+        (_, None) => PartialOp::Err(parse_kind(name).map_err(|_| "unknown op".to_string())?),
+        (_, Some(_)) => return Err("unknown op".to_string()),
+    };
+    Ok(op)
+}
+
+fn parse_number(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn parse_kind(s: &str) -> Result<io::ErrorKind, String> {
+    ERROR_KINDS
+        .iter()
+        .find(|kind| format!("{:?}", kind).eq_ignore_ascii_case(s))
+        .copied()
+        .ok_or_else(|| format!("unknown error kind `{}`", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sequence() {
+        let ops = PartialOp::parse_sequence(
+            "  limit:4 INTERRUPTED\n err:brokenpipe limit-then-err:3:TimedOut eof \
+             per-slice:2,0,4 drop-tail:1 buffer-too-large:16 delimiter:10 unlimited ",
+        )
+        .unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Limited(4),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Err(io::ErrorKind::BrokenPipe),
+                PartialOp::LimitedThenErr(3, io::ErrorKind::TimedOut),
+                PartialOp::Eof,
+                PartialOp::PerSlice(vec![2, 0, 4]),
+                PartialOp::DropTail(1),
+                PartialOp::BufferTooLarge(16),
+                PartialOp::Delimiter(b'\n'),
+                PartialOp::Unlimited,
+            ]
+        );
+        assert_eq!(PartialOp::parse_sequence("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut ops = vec![
+            PartialOp::Limited(7),
+            PartialOp::Unlimited,
+            PartialOp::LimitedThenErr(0, io::ErrorKind::UnexpectedEof),
+            PartialOp::Eof,
+            PartialOp::PerSlice(vec![5]),
+            PartialOp::PerSlice(vec![]),
+            PartialOp::DropTail(3),
+            PartialOp::BufferTooLarge(0),
+            PartialOp::Delimiter(255),
+            PartialOp::FlushDrops,
+        ];
+        ops.extend(ERROR_KINDS.iter().map(|&kind| PartialOp::Err(kind)));
+        let script = ops
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(PartialOp::parse_sequence(&script).unwrap(), ops);
+        for op in ops {
+            assert_eq!(op.to_string().parse::<PartialOp>().unwrap(), op);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = PartialOp::parse_sequence("limit:4 err:Nope unlimited").unwrap_err();
+        assert_eq!(err.token(), "err:Nope");
+        assert_eq!(err.index(), 1);
+        assert_eq!(
+            err.to_string(),
+            "invalid op `err:Nope` at index 1: unknown error kind `Nope`"
+        );
+
+        let cases = [
+            ("limit", "missing argument to `limit`"),
+            ("limit:-1", "invalid number `-1`"),
+            ("eof:1", "`eof` takes no argument"),
+            ("limit-then-err:3", "expected `limit-then-err:n:kind`"),
+            ("per-slice:1,,2", "invalid number ``"),
+            ("delimiter:256", "invalid byte `256`"),
+            ("bogus", "unknown op"),
+            ("bogus:1", "unknown op"),
+            // Stabilized after the minimum supported Rust version.
+            ("err:StorageFull", "unknown error kind `StorageFull`"),
+            ("HostUnreachable", "unknown op"),
+        ];
+        for &(token, reason) in &cases {
+            let err = token.parse::<PartialOp>().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("invalid op `{}` at index 0: {}", token, reason)
+            );
+        }
+    }
+}
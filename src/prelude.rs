@@ -0,0 +1,101 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A prelude for tests that use this crate.
+//!
+//! This re-exports the wrappers, the generators and the other helpers, along
+//! with a free constructor function for every kind of `PartialOp`, so that a
+//! single glob import covers most tests. The constructors are plain functions
+//! rather than re-exported variants, because a variant named `Err` would
+//! shadow `Result::Err`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::io::{self, Cursor, Read};
+//!
+//! use partial_io::prelude::*;
+//!
+//! let ops = vec![limited(2), err(io::ErrorKind::Interrupted), unlimited()];
+//! let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+//! let mut out = vec![0; 8];
+//! assert_eq!(reader.read(&mut out).unwrap(), 2);
+//! assert_eq!(reader.read(&mut out).unwrap_err().kind(), io::ErrorKind::Interrupted);
+//! assert_eq!(reader.read(&mut out).unwrap(), 2);
+//! ```
+
+use std::io;
+
+pub use crate::{
+    assert_equivalent, backoff_block, chain, collect_ops, every, first, from_read_trace,
+    percent_of_remaining_assuming_full, reverse, set_thread_local_ops, split_at, split_events,
+    synthetic, transfer, transfer_default, validate_script, DelimiterOps, Event, InMemoryDuplex,
+    OpObserver, PartialIo, PartialOp, PartialOpRng, PartialRead, PartialWrite, TimingObserver,
+    TransferStats, WrittenBytes,
+};
+#[cfg(feature = "tokio")]
+pub use crate::{copy, CopyStats, PartialAsyncRead, PartialAsyncWrite};
+
+/// Creates a `PartialOp::Limited` op.
+#[inline]
+pub const fn limited(n: usize) -> PartialOp {
+    PartialOp::Limited(n)
+}
+
+/// Creates a `PartialOp::Unlimited` op.
+#[inline]
+pub const fn unlimited() -> PartialOp {
+    PartialOp::Unlimited
+}
+
+/// Creates a `PartialOp::Err` op.
+#[inline]
+pub const fn err(kind: io::ErrorKind) -> PartialOp {
+    PartialOp::Err(kind)
+}
+
+/// Creates a `PartialOp::LimitedThenErr` op.
+#[inline]
+pub const fn limited_then_err(n: usize, kind: io::ErrorKind) -> PartialOp {
+    PartialOp::LimitedThenErr(n, kind)
+}
+
+/// Creates a `PartialOp::Eof` op.
+#[inline]
+pub const fn eof() -> PartialOp {
+    PartialOp::Eof
+}
+
+/// Creates a `PartialOp::PerSlice` op.
+#[inline]
+pub fn per_slice(caps: Vec<usize>) -> PartialOp {
+    PartialOp::PerSlice(caps)
+}
+
+/// Creates a `PartialOp::DropTail` op.
+#[inline]
+pub const fn drop_tail(n: usize) -> PartialOp {
+    PartialOp::DropTail(n)
+}
+
+/// Creates a `PartialOp::BufferTooLarge` op.
+#[inline]
+pub const fn buffer_too_large(max: usize) -> PartialOp {
+    PartialOp::BufferTooLarge(max)
+}
+
+/// Creates a `PartialOp::Delimiter` op.
+#[inline]
+pub const fn delimiter(byte: u8) -> PartialOp {
+    PartialOp::Delimiter(byte)
+}
+
+/// Creates a `PartialOp::FlushDrops` op.
+#[inline]
+pub const fn flush_drops() -> PartialOp {
+    PartialOp::FlushDrops
+}
@@ -8,11 +8,20 @@
 //! This module contains a reader wrapper that breaks its inputs up according to
 //! a provided iterator.
 
+use std::any;
 use std::cmp;
 use std::fmt;
-use std::io::{self, Read, Write};
+use std::io::{self, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::{make_ops, PartialOp};
+use crate::observer::OpObserver;
+use crate::ops::{
+    buffer_too_large, first_cap, injected_error, op_limit, EmptyOps, OpSource, Operation, Ops,
+    PartialIoStats,
+};
+use crate::PartialOp;
 
 /// A reader wrapper that breaks inner `Read` instances up according to the
 /// provided iterator.
@@ -35,7 +44,16 @@ use crate::{make_ops, PartialOp};
 /// ```
 pub struct PartialRead<R> {
     inner: R,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops,
+    strict_limit: bool,
+    carried_limit: Option<usize>,
+    post_seek_op: Option<PartialOp>,
+    seek_pending: bool,
+    last_eof: Option<EofSource>,
+    limit_only_above: Option<usize>,
+    deadline: Option<Instant>,
+    passthrough: bool,
+    inner_error_hook: Option<Box<dyn FnMut(io::Error) -> io::Error + Send>>,
 }
 
 impl<R> PartialRead<R>
@@ -43,27 +61,506 @@ where
     R: Read,
 {
     /// Creates a new `PartialRead` wrapper over the reader with the specified `PartialOp`s.
+    ///
+    /// The iterator can yield either `PartialOp`s or references to them, which
+    /// are cloned. This means that a shared `&'static [PartialOp]` table can be
+    /// passed in directly to several wrappers.
+    ///
+    /// Only the iterator needs to be `'static`. The inner reader can borrow,
+    /// e.g. a `&[u8]` or a locked `io::Stdin`; see the `stdio` example.
     pub fn new<I>(inner: R, iter: I) -> Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
+        Self::from_ops(inner, Ops::new(iter))
+    }
+
+    /// Creates a new `PartialRead` wrapper like `new`, but fails if `iter` is
+    /// empty.
+    ///
+    /// An empty sequence of ops makes the wrapper completely transparent, which
+    /// can hide a test that forgot to set up its ops. This draws the first op
+    /// up front to check for that, so it works with infinite iterators as
+    /// well.
+    pub fn new_nonempty<I>(inner: R, iter: I) -> Result<Self, EmptyOps>
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Ok(Self::from_ops(inner, Ops::new_nonempty(iter)?))
+    }
+
+    /// Creates a new `PartialRead` wrapper that forwards every read straight
+    /// to `inner`.
+    ///
+    /// This keeps the wrapper type in place while making it inert, e.g. to
+    /// measure a baseline without partial-io getting in the way. Reads skip
+    /// all of the wrapper's bookkeeping, so they cost next to nothing over
+    /// reading from `inner` directly. Setting ops with `set_ops` turns the
+    /// wrapper back into a regular one.
+    pub fn passthrough(inner: R) -> Self {
+        let mut reader = Self::new(inner, Vec::<PartialOp>::new());
+        reader.passthrough = true;
+        reader
+    }
+
+    /// Creates a new `PartialRead` wrapper that draws its ops from the
+    /// thread-local ops set with `set_thread_local_ops`.
+    ///
+    /// Each op is drawn from the ops of the thread calling `read`, rather than
+    /// the one that created the wrapper, when it is needed. This lets shared
+    /// setup code create the wrappers while each thread controls its own
+    /// schedule. While the thread's ops are empty, reads behave like
+    /// `PartialOp::Unlimited`, and ops set later are picked up by the following
+    /// reads. Setting ops with `set_ops` stops drawing from the thread-local
+    /// ops.
+    ///
+    /// See [`set_thread_local_ops`](fn.set_thread_local_ops.html) for an
+    /// example.
+    pub fn from_thread_local(inner: R) -> Self {
+        Self::from_ops(inner, Ops::thread_local())
+    }
+
+    /// Creates a new `PartialRead` wrapper whose reads fail with `kind` while
+    /// `flag` is set.
+    ///
+    /// The flag is checked before every read, and a read that fails because of
+    /// it doesn't consume an op. Other threads can set and clear the flag at
+    /// any time, e.g. to simulate the network going down and coming back up.
+    /// Checking it costs a single relaxed atomic load per read.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let down = Arc::new(AtomicBool::new(false));
+    /// let mut reader = PartialRead::with_fail_flag(
+    ///     Cursor::new(vec![1, 2, 3]),
+    ///     vec![PartialOp::Limited(1)],
+    ///     down.clone(),
+    ///     io::ErrorKind::ConnectionReset,
+    /// );
+    /// let mut out = vec![0; 8];
+    ///
+    /// down.store(true, Ordering::Relaxed);
+    /// let err = reader.read(&mut out).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    ///
+    /// down.store(false, Ordering::Relaxed);
+    /// assert_eq!(reader.read(&mut out).unwrap(), 1);
+    /// ```
+    pub fn with_fail_flag<I>(inner: R, iter: I, flag: Arc<AtomicBool>, kind: io::ErrorKind) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        let mut reader = Self::new(inner, iter);
+        reader.ops.set_fail_flag(flag, kind);
+        reader
+    }
+
+    /// Creates a new `PartialRead` from the state shared with
+    /// `PartialAsyncRead`.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn from_state(state: ReadState<R>) -> Self {
+        let mut reader = Self::from_ops(state.inner, state.ops);
+        reader.strict_limit = state.strict_limit;
+        reader.carried_limit = state.carried_limit;
+        reader.last_eof = state.last_eof;
+        reader.deadline = state.deadline;
+        reader
+    }
+
+    /// Returns the state shared with `PartialAsyncRead`, dropping the rest.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn into_state(self) -> ReadState<R> {
+        ReadState {
+            inner: self.inner,
+            ops: self.ops,
+            strict_limit: self.strict_limit,
+            carried_limit: self.carried_limit,
+            last_eof: self.last_eof,
+            deadline: self.deadline,
+        }
+    }
+
+    /// Creates a new `PartialRead` wrapper that replays a trace of read
+    /// results.
+    ///
+    /// Each result is turned into an op with
+    /// [`PartialOp::from_result`](enum.PartialOp.html#method.from_result):
+    /// `Ok(0)` becomes an injected end of file, other successes become limits
+    /// and errors are injected with the same kind.
+    pub fn from_results<I>(inner: R, results: I) -> Self
+    where
+        I: IntoIterator<Item = io::Result<usize>>,
+    {
+        let ops: Vec<_> = results
+            .into_iter()
+            .map(|res| PartialOp::from_result(&res))
+            .collect();
+        Self::new(inner, ops)
+    }
+
+    fn from_ops(inner: R, ops: Ops) -> Self {
         PartialRead {
             inner,
-            ops: make_ops(iter),
+            ops,
+            strict_limit: false,
+            carried_limit: None,
+            post_seek_op: None,
+            seek_pending: false,
+            last_eof: None,
+            limit_only_above: None,
+            deadline: None,
+            passthrough: false,
+            inner_error_hook: None,
         }
     }
 
     /// Sets the `PartialOp`s for this reader.
+    ///
+    /// An error still pending from an applied `PartialOp::LimitedThenErr` is
+    /// part of that op, so it is kept and fails the next read before the new
+    /// ops apply.
     pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.ops.set(iter);
+        self.carried_limit = None;
+        self.passthrough = false;
+        self
+    }
+
+    /// Sets the `PartialOp`s for this reader, and starts counting consumed ops
+    /// over from zero.
+    ///
+    /// This is like `set_ops`, but also resets `ops_consumed` and `stats`, so
+    /// that a reader can be reused to run a test again from scratch.
+    /// Recording, observers and the other settings stay in place.
+    pub fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.set_ops(iter);
+        self.ops.reset_counts();
+        self
+    }
+
+    /// Discards the remaining `PartialOp`s and forwards every read from now on
+    /// straight to the inner reader.
+    ///
+    /// This is useful for tests in phases, where setting up needs partial
+    /// behavior but tearing down should see a clean reader. Unlike setting an
+    /// empty sequence of ops, this also makes reads skip the wrapper's
+    /// bookkeeping like a [`passthrough`](#method.passthrough) reader does, so
+    /// a strictly enforced limit in progress, the post-seek op and the fail
+    /// flag no longer apply either. Setting ops with `set_ops` undoes this.
+    pub fn go_transparent(&mut self) -> &mut Self {
+        self.set_ops(Vec::<PartialOp>::new());
+        self.passthrough = true;
+        self
+    }
+
+    /// Sets whether `PartialOp::Limited` caps are enforced strictly.
+    ///
+    /// By default, a `Limited(n)` op is used up by a single read, even if the
+    /// inner reader returns fewer than `n` bytes. In strict mode, whatever is
+    /// left of the cap carries over to the next read instead of a new op being
+    /// consumed, so `Limited(n)` gates up to `n` bytes across however many
+    /// underlying reads it takes. A read that returns `Ok(0)` ends the carry.
+    ///
+    /// The reads that continue a carried cap are part of the original op: they
+    /// don't count towards `ops_consumed` or the stats, and their bytes are
+    /// added to the op's entry in the recording instead of a new one.
+    pub fn set_strict_limit(&mut self, strict: bool) -> &mut Self {
+        self.strict_limit = strict;
+        if !strict {
+            self.carried_limit = None;
+        }
+        self
+    }
+
+    /// Sets an op to apply to the first read following each `seek`.
+    ///
+    /// This models a device whose first read after a seek always behaves in a
+    /// particular way, e.g. returns a short read. The post-seek op is applied
+    /// instead of the next op from the iterator, which resumes on subsequent
+    /// reads. Pass `None` to stop injecting an op after seeks.
+    ///
+    /// The post-seek op isn't part of the script, so applying it doesn't count
+    /// towards `ops_consumed` or the stats. It is still recorded and passed to
+    /// observers like any other op.
+    pub fn set_post_seek_op(&mut self, op: Option<PartialOp>) -> &mut Self {
+        self.post_seek_op = op;
+        self
+    }
+
+    /// Only applies `PartialOp`s to reads into buffers larger than
+    /// `threshold` bytes.
+    ///
+    /// Reads into smaller buffers are forwarded to the inner reader unmodified,
+    /// without consuming an op. This targets the code paths that fill large
+    /// buffers, without small control-message reads using up scripted ops. For
+    /// vectored reads, the total length of the buffers is compared. Pass `None`
+    /// to apply ops to every read again.
+    pub fn set_limit_only_above(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.limit_only_above = threshold;
+        self
+    }
+
+    /// Sets a point in time after which every read fails with `WouldBlock`.
+    ///
+    /// Until the deadline, reads behave normally. Once it has passed, reads
+    /// return `WouldBlock` without consuming an op, modeling a connection that
+    /// stalled. Pass `None` to clear the deadline.
+    ///
+    /// This depends on the wall clock, so tests using it aren't deterministic.
+    /// It is intended for soak and integration tests.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.deadline = deadline;
         self
     }
 
+    /// Returns the maximum number of bytes the next read can return, without
+    /// consuming an op.
+    ///
+    /// This is `Some(n)` for a `Limited(n)` op, `Some(usize::MAX)` if the next
+    /// read is unlimited, `Some(0)` for an injected end of file and `None` if
+    /// the next read will fail with an error. It accounts for a post-seek op or
+    /// a strict limit carried over, but not for `set_limit_only_above`.
+    pub fn next_limit(&mut self) -> Option<usize> {
+        if self.seek_pending {
+            if let Some(op) = &self.post_seek_op {
+                return op_limit(Some(op));
+            }
+        }
+        if let Some(n) = self.carried_limit {
+            return Some(n);
+        }
+        op_limit(self.ops.peek())
+    }
+
+    /// Adds an observer to notify about every op this reader applies.
+    ///
+    /// Any number of observers can be added, and they coexist with recording.
+    /// See [`OpObserver`] for more.
+    ///
+    /// [`OpObserver`]: trait.OpObserver.html
+    pub fn add_observer(&mut self, observer: impl OpObserver + 'static) -> &mut Self {
+        self.ops.add_observer(Box::new(observer));
+        self
+    }
+
+    /// Skips up to `n` ops without performing any IO, returning how many were
+    /// actually skipped.
+    ///
+    /// Fewer than `n` ops are skipped if the ops run out. Skipped ops count
+    /// towards `ops_consumed`, but aren't recorded. This can be used to resume
+    /// a long script partway through, e.g. from a checkpoint.
+    pub fn skip_ops(&mut self, n: usize) -> usize {
+        self.ops.skip_ops(n)
+    }
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    pub fn ops_consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns counts of the `PartialOp`s applied so far, by kind.
+    ///
+    /// See [`PartialIoStats`](struct.PartialIoStats.html) for more.
+    pub fn stats(&self) -> PartialIoStats {
+        self.ops.stats()
+    }
+
+    /// Panics when this reader is dropped if any of its `PartialOp`s were
+    /// never consumed.
+    ///
+    /// This catches tests that don't exercise all of the partial behavior they
+    /// set up. The check only applies when the number of remaining ops is known
+    /// exactly (e.g. for a `Vec`), so unbounded iterators are exempt, and it is
+    /// skipped if the thread is already panicking. Consuming the wrapper with
+    /// `into_inner` counts as dropping it.
+    ///
+    /// If the wrapper is dropped while the thread is panicking and this or
+    /// recording is enabled, a one-line summary of the ops consumed, bytes
+    /// transferred and ops remaining is printed to stderr instead.
+    pub fn expect_all_consumed(&mut self) -> &mut Self {
+        self.ops.expect_all_consumed();
+        self
+    }
+
+    /// Sets whether applying a `PartialOp::Err(WouldBlock)` op panics.
+    ///
+    /// A `WouldBlock` error is only meaningful to non-blocking code that waits
+    /// for readiness and retries, so scripting one for this synchronous
+    /// wrapper is almost always a mistake: nothing will poll it again, and a
+    /// blocking caller may spin on it. With this set, such an op panics with a
+    /// message pointing to `PartialAsyncRead` instead. The default is to
+    /// return the error like any other.
+    ///
+    /// This also covers the error injected by `LimitedThenErr(_, WouldBlock)`.
+    pub fn forbid_would_block_sync(&mut self, forbid: bool) -> &mut Self {
+        self.ops.forbid_would_block(forbid);
+        self
+    }
+
+    /// Sets a hook that is called with every error returned by the inner
+    /// reader, before this reader returns it.
+    ///
+    /// The hook returns the error to pass on, so it can count, log or tag inner
+    /// errors, or replace them with a different error altogether. This makes
+    /// it possible to tell failures injected by the ops apart from unexpected
+    /// failures of the inner reader. Injected errors, including the ones from
+    /// `fail_while` and `set_poison_on_error`, aren't passed to the hook.
+    /// Setting a hook replaces the previous one.
+    ///
+    /// The hook is dropped when converting to a `PartialAsyncRead`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// struct Broken;
+    ///
+    /// impl Read for Broken {
+    ///     fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+    ///         Err(io::Error::new(io::ErrorKind::Other, "disk on fire"))
+    ///     }
+    /// }
+    ///
+    /// let mut reader = PartialRead::new(Broken, vec![PartialOp::Limited(1)]);
+    /// reader.on_inner_error(|err| io::Error::new(err.kind(), format!("inner: {}", err)));
+    /// let err = reader.read(&mut [0; 8]).unwrap_err();
+    /// assert_eq!(err.to_string(), "inner: disk on fire");
+    /// ```
+    pub fn on_inner_error<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnMut(io::Error) -> io::Error + Send + 'static,
+    {
+        self.inner_error_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets whether a fatal injected error poisons this reader.
+    ///
+    /// Once a `PartialOp::Err` op with a kind other than `WouldBlock` or
+    /// `Interrupted` has failed a read, every later read fails with
+    /// `ErrorKind::NotConnected` without consuming an op. This models a
+    /// connection that is unusable after its first error, and catches code that
+    /// keeps using a transport after a fatal error. Disabling this clears the
+    /// poisoned state.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = vec![PartialOp::Err(io::ErrorKind::BrokenPipe), PartialOp::Unlimited];
+    /// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+    /// reader.set_poison_on_error(true);
+    /// let mut out = vec![0; 8];
+    /// assert_eq!(reader.read(&mut out).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    /// assert_eq!(reader.read(&mut out).unwrap_err().kind(), io::ErrorKind::NotConnected);
+    /// ```
+    pub fn set_poison_on_error(&mut self, poison: bool) -> &mut Self {
+        self.ops.set_poison_on_error(poison);
+        self
+    }
+
+    /// Sets whether to record the `PartialOp`s applied by this reader.
+    ///
+    /// While recording is enabled, every `read` call appends the op it applied
+    /// and the number of bytes it transferred to a log, which can be inspected
+    /// with `recorded` or written out with `dump_recorded`. Once the ops have
+    /// run out, `PartialOp::Unlimited` is recorded. Errors are recorded as
+    /// transferring 0 bytes. Disabling recording discards the log.
+    pub fn set_recording(&mut self, record: bool) -> &mut Self {
+        self.ops.set_recording(record);
+        self
+    }
+
+    /// Returns the ops recorded so far, along with the number of bytes each
+    /// transferred.
+    ///
+    /// This is empty unless recording has been enabled with `set_recording`.
+    pub fn recorded(&self) -> &[(PartialOp, usize)] {
+        self.ops.recorded()
+    }
+
+    /// Removes the ops recorded so far from the log, yielding them in order.
+    ///
+    /// Recording carries on afterwards, so calling this between reads
+    /// processes the log as it grows and yields each op exactly once, without
+    /// holding on to the whole history. The log is emptied even if the
+    /// iterator is dropped before being exhausted. This yields nothing unless
+    /// recording has been enabled with `set_recording`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = vec![PartialOp::Limited(1), PartialOp::Limited(2)];
+    /// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+    /// reader.set_recording(true);
+    ///
+    /// let mut out = vec![0; 8];
+    /// reader.read(&mut out).unwrap();
+    /// let events: Vec<_> = reader.drain_events().collect();
+    /// assert_eq!(events, vec![(PartialOp::Limited(1), 1)]);
+    /// reader.read(&mut out).unwrap();
+    /// let events: Vec<_> = reader.drain_events().collect();
+    /// assert_eq!(events, vec![(PartialOp::Limited(2), 2)]);
+    /// assert!(reader.recorded().is_empty());
+    /// ```
+    pub fn drain_events(&mut self) -> impl Iterator<Item = (PartialOp, usize)> + '_ {
+        self.ops.drain_recorded()
+    }
+
+    /// Writes the recorded ops to `w`, one per line.
+    ///
+    /// Each line consists of the op's `Display` form and the number of bytes
+    /// transferred, separated by a space, e.g. `limit:4 3` or
+    /// `err:WouldBlock 0`. This format is stable, so logs from different runs
+    /// can be diffed. If recording isn't enabled, nothing is written.
+    pub fn dump_recorded(&self, w: &mut impl Write) -> io::Result<()> {
+        self.ops.dump_recorded(w)
+    }
+
+    /// Returns whether the most recent read returning `Ok(0)` did so because
+    /// of a `PartialOp::Eof` op, rather than the inner reader.
+    ///
+    /// This is `false` if no read has returned `Ok(0)` yet. Reads that return
+    /// data or fail don't affect it. The recorded ops tell the two apart as
+    /// well, since an injected end of file is recorded as `eof 0`.
+    pub fn last_eof_was_injected(&self) -> bool {
+        self.last_eof == Some(EofSource::Injected)
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.inner
@@ -78,24 +575,347 @@ where
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    fn next_op(&mut self) -> (Option<PartialOp>, OpSource) {
+        if self.seek_pending {
+            self.seek_pending = false;
+            if let Some(op) = &self.post_seek_op {
+                return (Some(op.clone()), OpSource::Injected);
+            }
+        }
+        match self.carried_limit.take() {
+            Some(n) => (Some(PartialOp::Limited(n)), OpSource::Carried),
+            None => (self.ops.next(), OpSource::Script),
+        }
+    }
 }
 
 impl<R> Read for PartialRead<R>
 where
     R: Read,
 {
+    // The rest of the read path lives in `read_partial`, so that this stays
+    // small enough to be inlined for passthrough readers.
+    #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.ops.next() {
+        if self.passthrough {
+            return self.inner.read(buf);
+        }
+        self.read_partial(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.passthrough {
+            return self.inner.read_vectored(bufs);
+        }
+        self.ops.check_fail_flag(Operation::Read)?;
+        self.ops.check_poisoned(Operation::Read)?;
+        check_deadline(self.deadline)?;
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        if self.is_below_limit_threshold(total) || self.fits_next_op(total) {
+            let res = self.inner.read_vectored(bufs);
+            return self.finish_forwarded(res);
+        }
+        let (op, source) = self.next_op();
+        match &op {
+            Some(PartialOp::PerSlice(caps)) => {
+                let res = read_per_slice(&mut self.inner, caps, bufs);
+                let res = self.handle_inner_error(res);
+                return self.finish_read(op, source, res);
+            }
+            Some(PartialOp::BufferTooLarge(max)) if total > *max => {
+                let err = buffer_too_large(*max);
+                return self.finish_read(op, source, Err(err));
+            }
+            _ => {}
+        }
+        // Like the default implementation, read into the first non-empty
+        // buffer. Empty buffers are skipped so that the op applies to data,
+        // which makes this read behave exactly like a `read` into that buffer:
+        // the same limit, the same `Ok(0)` for `Limited(0)`, and a count of
+        // the bytes placed in it.
+        match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.read_with(op, source, buf),
+            None => self.read_with(op, source, &mut []),
+        }
+    }
+}
+
+impl<R> PartialRead<R>
+where
+    R: Read,
+{
+    fn read_partial(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.ops.check_fail_flag(Operation::Read)?;
+        self.ops.check_poisoned(Operation::Read)?;
+        check_deadline(self.deadline)?;
+        if self.is_below_limit_threshold(buf.len()) || self.fits_next_op(buf.len()) {
+            let res = read_inner(&mut self.inner, buf);
+            return self.finish_forwarded(res);
+        }
+        let (op, source) = self.next_op();
+        self.read_with(op, source, buf)
+    }
+
+    fn read_with(
+        &mut self,
+        op: Option<PartialOp>,
+        source: OpSource,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let res = match op {
             Some(PartialOp::Limited(n)) => {
                 let len = cmp::min(n, buf.len());
-                self.inner.read(&mut buf[..len])
+                let res = read_inner(&mut self.inner, &mut buf[..len]);
+                if self.strict_limit {
+                    self.carried_limit = carry_limit(n, &res);
+                }
+                res
+            }
+            Some(PartialOp::LimitedThenErr(n, _)) | Some(PartialOp::DropTail(n)) => {
+                let len = cmp::min(n, buf.len());
+                read_inner(&mut self.inner, &mut buf[..len])
             }
-            Some(PartialOp::Err(err)) => Err(io::Error::new(
-                err,
-                "error during read, generated by partial-io",
-            )),
-            Some(PartialOp::Unlimited) | None => self.inner.read(buf),
+            Some(PartialOp::Err(err)) => {
+                let err = injected_error(err, Operation::Read);
+                return self.finish_read(op, source, Err(err));
+            }
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let len = first_cap(caps, buf.len());
+                read_inner(&mut self.inner, &mut buf[..len])
+            }
+            Some(PartialOp::Delimiter(delimiter)) => {
+                read_until_delimiter(&mut self.inner, buf, delimiter)
+            }
+            Some(PartialOp::BufferTooLarge(max)) if buf.len() > max => {
+                return self.finish_read(op, source, Err(buffer_too_large(max)));
+            }
+            Some(PartialOp::BufferTooLarge(_))
+            | Some(PartialOp::Unlimited)
+            | Some(PartialOp::FlushDrops)
+            | None => read_inner(&mut self.inner, buf),
+        };
+        let res = self.handle_inner_error(res);
+        self.finish_read(op, source, res)
+    }
+
+    /// Passes an error returned by the inner reader through the hook set with
+    /// `on_inner_error`, if any.
+    #[inline]
+    fn handle_inner_error(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        match (res, &mut self.inner_error_hook) {
+            (Err(err), Some(hook)) => Err(hook(err)),
+            (res, _) => res,
+        }
+    }
+
+    /// Returns whether the next op is a `BufferTooLarge` that a `len`-byte
+    /// read fits within, so that the read should bypass the ops.
+    fn fits_next_op(&mut self, len: usize) -> bool {
+        let overridden = self.seek_pending && self.post_seek_op.is_some();
+        !overridden && self.carried_limit.is_none() && self.ops.fits_buffer_limit(len)
+    }
+
+    #[inline]
+    fn is_below_limit_threshold(&self, len: usize) -> bool {
+        self.limit_only_above
+            .map_or(false, |threshold| len <= threshold)
+    }
+
+    /// Updates the state after a read that bypassed the ops.
+    fn finish_forwarded(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        let res = self.handle_inner_error(res);
+        if let Some(source) = EofSource::of(None, &res) {
+            self.last_eof = Some(source);
+        }
+        res
+    }
+
+    fn finish_read(
+        &mut self,
+        op: Option<PartialOp>,
+        source: OpSource,
+        res: io::Result<usize>,
+    ) -> io::Result<usize> {
+        if let Some(eof_source) = EofSource::of(op.as_ref(), &res) {
+            self.last_eof = Some(eof_source);
         }
+        self.ops
+            .complete_from(source, op, res.as_ref().ok().copied());
+        res
+    }
+}
+
+/// The state that `PartialRead` and `PartialAsyncRead` have in common, for
+/// converting between them.
+#[cfg(feature = "tokio")]
+pub(crate) struct ReadState<R> {
+    pub(crate) inner: R,
+    pub(crate) ops: Ops,
+    pub(crate) strict_limit: bool,
+    pub(crate) carried_limit: Option<usize>,
+    pub(crate) last_eof: Option<EofSource>,
+    pub(crate) deadline: Option<Instant>,
+}
+
+/// Returns a `WouldBlock` error if `deadline` has passed.
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> io::Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "read deadline passed, generated by partial-io",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Reads from `inner` into `buf`.
+///
+/// In debug builds, this panics if `inner` claims to have read more bytes than
+/// `buf` holds, which a correct `Read` impl never does. This catches buggy
+/// readers, such as hand-written mocks, before the bogus count causes trouble
+/// further along.
+#[inline]
+pub(crate) fn read_inner<R>(inner: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: Read + ?Sized,
+{
+    let res = inner.read(buf);
+    if let Ok(n) = res {
+        debug_assert!(
+            n <= buf.len(),
+            "partial-io: inner reader returned {} byte(s) for a {}-byte buffer",
+            n,
+            buf.len()
+        );
+    }
+    res
+}
+
+/// Reads into each of `bufs` in turn, limiting slice `i` to `caps[i]` bytes.
+///
+/// Slices without a corresponding cap are unlimited, and extra caps are
+/// ignored. This stops at the first read that doesn't fill its slice up to the
+/// cap.
+pub(crate) fn read_per_slice<R>(
+    inner: &mut R,
+    caps: &[usize],
+    bufs: &mut [IoSliceMut<'_>],
+) -> io::Result<usize>
+where
+    R: Read + ?Sized,
+{
+    let mut total = 0;
+    for (i, buf) in bufs.iter_mut().enumerate() {
+        let len = caps
+            .get(i)
+            .map_or(buf.len(), |&cap| cmp::min(cap, buf.len()));
+        match read_inner(inner, &mut buf[..len]) {
+            Ok(n) => {
+                total += n;
+                if n < len {
+                    break;
+                }
+            }
+            // Report the data read so far. The error is likely to come up
+            // again on the next call.
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Reads into `buf` one byte at a time, stopping right after `delimiter`.
+///
+/// This also stops at the end of `buf` or at the end of file. An error after
+/// some bytes have been read ends the read early instead of being returned, so
+/// that the bytes aren't lost.
+pub(crate) fn read_until_delimiter<R>(
+    inner: &mut R,
+    buf: &mut [u8],
+    delimiter: u8,
+) -> io::Result<usize>
+where
+    R: Read + ?Sized,
+{
+    let mut total = 0;
+    while total < buf.len() {
+        match read_inner(inner, &mut buf[total..=total]) {
+            Ok(0) => break,
+            Ok(_) => {
+                total += 1;
+                if buf[total - 1] == delimiter {
+                    break;
+                }
+            }
+            // The error is likely to come up again on the next call.
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Where a read returning `Ok(0)` got it from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum EofSource {
+    Inner,
+    Injected,
+}
+
+impl EofSource {
+    /// Returns the source of `res` if it is `Ok(0)`, given the op that was
+    /// applied.
+    #[inline]
+    pub(crate) fn of(op: Option<&PartialOp>, res: &io::Result<usize>) -> Option<Self> {
+        match (op, res) {
+            (Some(PartialOp::Eof), Ok(0)) => Some(EofSource::Injected),
+            (_, Ok(0)) => Some(EofSource::Inner),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the part of a `Limited(n)` cap left over after a read, if any.
+pub(crate) fn carry_limit(n: usize, res: &io::Result<usize>) -> Option<usize> {
+    match *res {
+        Ok(0) => None,
+        Ok(read) if read < n => Some(n - read),
+        Ok(_) => None,
+        // Nothing was transferred, so the whole cap still applies.
+        Err(_) => Some(n),
+    }
+}
+
+impl<R> Seek for PartialRead<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let res = self.inner.seek(pos);
+        if res.is_ok() {
+            self.seek_pending = true;
+        }
+        res
+    }
+}
+
+impl<R> PartialRead<R>
+where
+    R: Seek,
+{
+    /// Returns the current position of the inner reader.
+    ///
+    /// This seeks the inner reader by `SeekFrom::Current(0)`, which leaves its
+    /// position as it is and doesn't consume an op or otherwise affect the
+    /// wrapper. Together with recording, this makes it possible to tell the
+    /// offset at which each op was applied. On some streams even such a no-op
+    /// seek has side effects, e.g. flushing a `BufWriter` or discarding the
+    /// buffer of a `BufReader`.
+    pub fn position(&mut self) -> io::Result<u64> {
+        self.inner.stream_position()
     }
 }
 
@@ -115,27 +935,901 @@ where
     }
 }
 
-impl<R> fmt::Debug for PartialRead<R>
-where
-    R: fmt::Debug,
-{
+// This prints the name of the inner type rather than its value, so that it
+// doesn't require the inner reader to implement `Debug`.
+impl<R> fmt::Debug for PartialRead<R> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PartialRead")
-            .field("inner", &self.inner)
+            .field("inner", &format_args!("{}", any::type_name::<R>()))
+            .field("ops_consumed", &self.ops.consumed())
             .finish()
     }
 }
 
+/// Creates a reader that reads `first` to the end, then `second`, with a
+/// single sequence of `PartialOp`s applied to both.
+///
+/// This is `first.chain(second)` wrapped in a `PartialRead`, so the ops carry
+/// on across the boundary rather than starting over. Every read reads from only
+/// one of the two readers: a `Limited` op that straddles the boundary returns
+/// the rest of `first`, and the next op applies to `second`. The transition
+/// itself neither consumes an extra op nor returns a spurious `Ok(0)`, since
+/// the read that finds `first` exhausted goes on to read from `second` within
+/// the same op.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use partial_io::PartialOp;
+///
+/// let ops = vec![PartialOp::Limited(2), PartialOp::Limited(2), PartialOp::Limited(2)];
+/// let mut reader = partial_io::chain(&b"abc"[..], &b"def"[..], ops);
+/// let mut out = vec![0; 8];
+/// assert_eq!(reader.read(&mut out).unwrap(), 2);
+/// // Only the rest of the first reader.
+/// assert_eq!(reader.read(&mut out[2..]).unwrap(), 1);
+/// assert_eq!(reader.read(&mut out[3..]).unwrap(), 2);
+/// assert_eq!(&out[..5], b"abcde");
+/// ```
+pub fn chain<R1, R2, I>(first: R1, second: R2, iter: I) -> PartialRead<io::Chain<R1, R2>>
+where
+    R1: Read,
+    R2: Read,
+    I: IntoIterator + 'static,
+    I::Item: Into<PartialOp>,
+    I::IntoIter: Send,
+{
+    PartialRead::new(first.chain(second), iter)
+}
+
+/// Creates a reader of `len` bytes generated by `pattern`, with `PartialOp`s
+/// applied to it.
+///
+/// `pattern` is called with the offset of each byte in the stream and returns
+/// that byte, so the data can be checked after it has been reassembled without
+/// having to set up a real source. The reader returns EOF after `len` bytes.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use partial_io::PartialOp;
+///
+/// let ops = vec![PartialOp::Limited(3)];
+/// let mut reader = partial_io::synthetic(|i| i as u8, 5, ops);
+/// let mut out = vec![0; 8];
+/// assert_eq!(reader.read(&mut out).unwrap(), 3);
+/// assert_eq!(reader.read(&mut out[3..]).unwrap(), 2);
+/// assert_eq!(reader.read(&mut out[5..]).unwrap(), 0);
+/// assert_eq!(&out[..5], &[0, 1, 2, 3, 4]);
+/// ```
+pub fn synthetic<F, I>(pattern: F, len: u64, iter: I) -> PartialRead<Synthetic<F>>
+where
+    F: FnMut(u64) -> u8,
+    I: IntoIterator + 'static,
+    I::Item: Into<PartialOp>,
+    I::IntoIter: Send,
+{
+    let inner = Synthetic {
+        pattern,
+        pos: 0,
+        len,
+    };
+    PartialRead::new(inner, iter)
+}
+
+/// A reader that generates its data from a pattern, as created by
+/// [`synthetic`].
+///
+/// [`synthetic`]: fn.synthetic.html
+pub struct Synthetic<F> {
+    pattern: F,
+    pos: u64,
+    len: u64,
+}
+
+impl<F> Synthetic<F> {
+    /// Returns the offset of the next byte to be generated.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Returns the number of bytes left before EOF.
+    #[inline]
+    pub fn remaining(&self) -> u64 {
+        self.len - self.pos
+    }
+}
+
+impl<F> Read for Synthetic<F>
+where
+    F: FnMut(u64) -> u8,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len() as u64, self.remaining()) as usize;
+        for byte in &mut buf[..n] {
+            *byte = (self.pattern)(self.pos);
+            self.pos += 1;
+        }
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use std::fs::File;
+    use std::io::Cursor;
+    use std::iter;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use std::thread;
 
     use crate::tests::assert_send;
+    use crate::BufferTooLargeError;
 
     #[test]
     fn test_sendable() {
         assert_send::<PartialRead<File>>();
     }
+
+    #[test]
+    fn test_strict_limit() {
+        // The inner reader never returns more than 3 bytes at a time.
+        let inner = PartialRead::new(
+            Cursor::new((0..16).collect::<Vec<u8>>()),
+            iter::repeat(PartialOp::Limited(3)),
+        );
+        let mut partial_read =
+            PartialRead::new(inner, vec![PartialOp::Limited(5), PartialOp::Limited(1)]);
+        partial_read.set_strict_limit(true);
+
+        let mut out = vec![0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        // The remaining 2 bytes of the Limited(5) carry over.
+        assert_eq!(partial_read.read(&mut out[3..]).unwrap(), 2);
+        assert_eq!(partial_read.read(&mut out[5..]).unwrap(), 1);
+        assert_eq!(&out[..6], &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_strict_limit_counts_one_op() {
+        // The inner reader never returns more than 2 bytes at a time.
+        let inner = PartialRead::new(
+            Cursor::new((0..16).collect::<Vec<u8>>()),
+            iter::repeat(PartialOp::Limited(2)),
+        );
+        let mut partial_read = PartialRead::new(inner, vec![PartialOp::Limited(10)]);
+        partial_read.set_strict_limit(true).set_recording(true);
+
+        let mut out = [0; 16];
+        for i in 0..5 {
+            assert_eq!(partial_read.read(&mut out[i * 2..]).unwrap(), 2);
+        }
+        // The carried reads are part of the one `Limited(10)` op.
+        assert_eq!(partial_read.ops_consumed(), 1);
+        assert_eq!(partial_read.stats().limited, 1);
+        assert_eq!(partial_read.recorded(), &[(PartialOp::Limited(10), 10)]);
+        assert_eq!(&out[..10], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_post_seek_op() {
+        let ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Limited(4),
+            PartialOp::Limited(4),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new((0..32).collect::<Vec<u8>>()), ops);
+        partial_read.set_post_seek_op(Some(PartialOp::Limited(1)));
+
+        let mut out = vec![0; 32];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 4);
+
+        assert_eq!(partial_read.seek(SeekFrom::Start(8)).unwrap(), 8);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 8);
+        // The normal op stream resumes after the post-seek read.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 4);
+        assert_eq!(&out[..4], &[9, 10, 11, 12]);
+
+        assert_eq!(partial_read.seek(SeekFrom::Current(-1)).unwrap(), 12);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 12);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 4);
+        // The op stream has been exhausted.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 15);
+        // Only the scripted ops count.
+        assert_eq!(partial_read.ops_consumed(), 3);
+        assert_eq!(partial_read.stats().limited, 3);
+        assert_eq!(partial_read.stats().unlimited, 1);
+    }
+
+    #[test]
+    fn test_position() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(5),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new((0..16).collect::<Vec<u8>>()), ops);
+        partial_read.set_post_seek_op(Some(PartialOp::Limited(1)));
+        let mut out = vec![0; 16];
+
+        assert_eq!(partial_read.position().unwrap(), 0);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.position().unwrap(), 3);
+        assert!(partial_read.read(&mut out).is_err());
+        assert_eq!(partial_read.position().unwrap(), 3);
+        // Unlike a seek through the wrapper, this doesn't trigger the
+        // post-seek op.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 5);
+        assert_eq!(partial_read.position().unwrap(), 8);
+        assert_eq!(partial_read.ops_consumed(), 3);
+    }
+
+    #[test]
+    fn test_dump_recorded() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(8),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4, 5]), ops);
+        let mut log = Vec::new();
+        partial_read.dump_recorded(&mut log).unwrap();
+        assert!(log.is_empty());
+
+        partial_read.set_recording(true);
+        let mut out = Vec::new();
+        partial_read.read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![1, 2, 3, 4, 5]);
+
+        partial_read.dump_recorded(&mut log).unwrap();
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            "limit:2 2\nerr:Interrupted 0\nlimit:8 3\nunlimited 0\n"
+        );
+    }
+
+    #[test]
+    fn test_from_thread_local() {
+        let run = |ops: Vec<PartialOp>| {
+            thread::spawn(move || {
+                crate::set_thread_local_ops(ops);
+                let mut partial_read =
+                    PartialRead::from_thread_local(Cursor::new((0..8).collect::<Vec<u8>>()));
+                let mut out = vec![0; 8];
+                let mut sizes = Vec::new();
+                loop {
+                    match partial_read.read(&mut out) {
+                        Ok(0) => break,
+                        Ok(n) => sizes.push(n),
+                        Err(err) => {
+                            assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+                            sizes.push(0);
+                        }
+                    }
+                }
+                sizes
+            })
+        };
+        let a = run(vec![PartialOp::Limited(1), PartialOp::Limited(3)]);
+        let b = run(vec![
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+            PartialOp::Limited(2),
+            PartialOp::Limited(2),
+        ]);
+        assert_eq!(a.join().unwrap(), vec![1, 3, 4]);
+        assert_eq!(b.join().unwrap(), vec![0, 2, 2, 2, 2]);
+
+        // The ops are drawn lazily, so ones set after running out are used.
+        let mut partial_read = PartialRead::from_thread_local(Cursor::new(vec![0; 8]));
+        let mut out = vec![0; 8];
+        crate::set_thread_local_ops(Vec::<PartialOp>::new());
+        assert_eq!(partial_read.read(&mut out[..2]).unwrap(), 2);
+        crate::set_thread_local_ops(vec![PartialOp::Limited(1)]);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_drain_events() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4, 5]), ops);
+        partial_read.set_recording(true);
+        assert_eq!(partial_read.drain_events().count(), 0);
+
+        let mut out = vec![0; 8];
+        let mut events = Vec::new();
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        partial_read.read(&mut out).unwrap_err();
+        events.extend(partial_read.drain_events());
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        events.extend(partial_read.drain_events());
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        // Dropping the iterator early still empties the log.
+        drop(partial_read.drain_events());
+        assert!(partial_read.recorded().is_empty());
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        events.extend(partial_read.drain_events());
+
+        assert_eq!(
+            events,
+            vec![
+                (PartialOp::Limited(1), 1),
+                (PartialOp::Err(io::ErrorKind::Interrupted), 0),
+                (PartialOp::Limited(2), 2),
+                (PartialOp::Unlimited, 1),
+            ]
+        );
+        assert!(partial_read.recorded().is_empty());
+    }
+
+    #[test]
+    fn test_non_strict_limit() {
+        let inner = PartialRead::new(
+            Cursor::new((0..16).collect::<Vec<u8>>()),
+            iter::repeat(PartialOp::Limited(3)),
+        );
+        let mut partial_read =
+            PartialRead::new(inner, vec![PartialOp::Limited(5), PartialOp::Limited(1)]);
+
+        let mut out = vec![0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.read(&mut out[3..]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_last_eof_was_injected() {
+        let ops = vec![PartialOp::Eof, PartialOp::Limited(2)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2]), ops);
+        partial_read.set_recording(true);
+        assert!(!partial_read.last_eof_was_injected());
+
+        let mut out = vec![0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 0);
+        assert!(partial_read.last_eof_was_injected());
+        // Reads that return data don't change it.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        assert!(partial_read.last_eof_was_injected());
+        assert_eq!(partial_read.read(&mut out).unwrap(), 0);
+        assert!(!partial_read.last_eof_was_injected());
+
+        assert_eq!(partial_read.recorded()[0], (PartialOp::Eof, 0));
+    }
+
+    #[test]
+    fn test_vectored_matches_scalar() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Limited(3),
+            PartialOp::Limited(0),
+            PartialOp::Eof,
+            PartialOp::Limited(1),
+        ];
+        let data: Vec<u8> = (0..16).collect();
+        let mut vectored = PartialRead::new(Cursor::new(data.clone()), ops.clone());
+        let mut scalar = PartialRead::new(Cursor::new(data), ops);
+
+        // A leading empty slice followed by two data slices, with the first
+        // one smaller or larger than the limit.
+        let mut sizes = Vec::new();
+        for &len in &[2, 4, 4, 4, 4] {
+            let (mut empty, mut a, mut b) = ([0; 0], vec![0; len], [0; 8]);
+            let mut bufs = [
+                IoSliceMut::new(&mut empty),
+                IoSliceMut::new(&mut a),
+                IoSliceMut::new(&mut b),
+            ];
+            let n = vectored.read_vectored(&mut bufs).unwrap();
+            sizes.push(n);
+
+            let mut expected = vec![0; len];
+            assert_eq!(scalar.read(&mut expected).unwrap(), n);
+            assert_eq!(a, expected);
+            assert_eq!(b, [0; 8]);
+            assert_eq!(
+                vectored.last_eof_was_injected(),
+                scalar.last_eof_was_injected()
+            );
+        }
+        assert_eq!(sizes, vec![2, 3, 0, 0, 1]);
+        assert_eq!(vectored.stats(), scalar.stats());
+        assert_eq!(vectored.get_ref().position(), 6);
+    }
+
+    #[test]
+    fn test_per_slice() {
+        let ops = vec![
+            PartialOp::PerSlice(vec![2, 0, 4]),
+            PartialOp::PerSlice(vec![1]),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new((0..16).collect::<Vec<u8>>()), ops);
+
+        let (mut a, mut b, mut c) = ([0; 5], [0; 5], [0; 5]);
+        let mut bufs = [
+            IoSliceMut::new(&mut a),
+            IoSliceMut::new(&mut b),
+            IoSliceMut::new(&mut c),
+        ];
+        assert_eq!(partial_read.read_vectored(&mut bufs).unwrap(), 6);
+        assert_eq!(a, [0, 1, 0, 0, 0]);
+        assert_eq!(b, [0; 5]);
+        assert_eq!(c, [2, 3, 4, 5, 0]);
+
+        // Slices past the end of the caps are unlimited.
+        let (mut a, mut b) = ([0; 3], [0; 3]);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        assert_eq!(partial_read.read_vectored(&mut bufs).unwrap(), 4);
+        assert_eq!(a, [6, 0, 0]);
+        assert_eq!(b, [7, 8, 9]);
+    }
+
+    #[test]
+    fn test_limit_only_above() {
+        let ops = vec![PartialOp::Limited(10), PartialOp::Limited(20)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![7; 4096]), ops);
+        partial_read.set_limit_only_above(Some(16));
+        partial_read.expect_all_consumed();
+
+        let mut out = vec![0; 1024];
+        assert_eq!(partial_read.read(&mut out[..4]).unwrap(), 4);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 10);
+        assert_eq!(partial_read.read(&mut out[..4]).unwrap(), 4);
+        assert_eq!(partial_read.read(&mut out[..16]).unwrap(), 16);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_next_limit() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1; 16]), ops);
+        partial_read.expect_all_consumed();
+
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.next_limit(), Some(3));
+        // Peeking doesn't consume the op.
+        assert_eq!(partial_read.next_limit(), Some(3));
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.next_limit(), None);
+        partial_read.read(&mut out).unwrap_err();
+        assert_eq!(partial_read.next_limit(), Some(usize::MAX));
+        assert_eq!(partial_read.read(&mut out).unwrap(), 8);
+        // The ops have run out, so reads are unlimited.
+        assert_eq!(partial_read.next_limit(), Some(usize::MAX));
+    }
+
+    #[test]
+    fn test_skip_ops() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1; 16]), ops);
+
+        assert_eq!(partial_read.skip_ops(2), 2);
+        assert_eq!(partial_read.ops_consumed(), 2);
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.ops_consumed(), 3);
+        // The ops have run out.
+        assert_eq!(partial_read.skip_ops(5), 0);
+        assert_eq!(partial_read.ops_consumed(), 3);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut partial_read =
+            PartialRead::new(Cursor::new(vec![1, 2, 3]), vec![PartialOp::Limited(1)]);
+        partial_read.set_deadline(Some(Instant::now()));
+
+        let mut out = vec![0; 8];
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        // The op wasn't consumed.
+        partial_read.set_deadline(None);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_debug_opaque_inner() {
+        struct Opaque;
+
+        impl Read for Opaque {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+        }
+
+        let partial_read = PartialRead::new(Opaque, vec![PartialOp::Limited(1)]);
+        let debug = format!("{:?}", partial_read);
+        assert!(debug.starts_with("PartialRead { inner: "), "{}", debug);
+        assert!(debug.contains("Opaque"), "{}", debug);
+        assert!(debug.ends_with("ops_consumed: 0 }"), "{}", debug);
+    }
+
+    #[test]
+    fn test_stats() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Other),
+            PartialOp::Limited(2),
+            PartialOp::Eof,
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4, 5]), ops);
+        let mut out = vec![0; 8];
+        for _ in 0..8 {
+            let _ = partial_read.read(&mut out);
+        }
+        assert_eq!(
+            partial_read.stats(),
+            PartialIoStats {
+                would_blocks: 2,
+                interrupted: 1,
+                other_errors: 1,
+                limited: 2,
+                unlimited: 1,
+                eofs: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let mut partial_read = PartialRead::passthrough(Cursor::new(vec![1, 2, 3, 4]));
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 4);
+        assert_eq!(partial_read.ops_consumed(), 0);
+
+        partial_read.get_mut().set_position(0);
+        partial_read.set_ops(vec![PartialOp::Limited(1)]);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_go_transparent() {
+        let ops = vec![PartialOp::Limited(2), PartialOp::Limited(1), PartialOp::Eof];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![7; 64]), ops);
+        partial_read.set_strict_limit(true).expect_all_consumed();
+        let mut out = vec![0; 64];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+
+        partial_read.go_transparent();
+        assert_eq!(partial_read.read(&mut out).unwrap(), 62);
+        assert_eq!(partial_read.ops_consumed(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "op drawn while the previous one is still in progress")]
+    fn test_reentrancy_guard() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        struct PanicOnce(bool);
+
+        impl Read for PanicOnce {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                if !self.0 {
+                    self.0 = true;
+                    panic!("misbehaving reader");
+                }
+                Ok(0)
+            }
+        }
+
+        let ops = vec![PartialOp::Limited(1), PartialOp::Limited(1)];
+        let mut partial_read = PartialRead::new(PanicOnce(false), ops);
+        let mut out = [0; 4];
+        // The first read panics while its op is in progress...
+        let res = panic::catch_unwind(AssertUnwindSafe(|| partial_read.read(&mut out)));
+        assert!(res.is_err());
+        // ...so drawing the next op trips the guard.
+        let _ = partial_read.read(&mut out);
+    }
+
+    #[test]
+    fn test_on_inner_error() {
+        struct FailEvery(usize);
+
+        impl Read for FailEvery {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0 += 1;
+                if self.0 % 2 == 0 {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "inner failure"));
+                }
+                buf[0] = 1;
+                Ok(1)
+            }
+        }
+
+        let ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::TimedOut),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(FailEvery(0), ops);
+        let (tx, rx) = mpsc::channel();
+        partial_read.on_inner_error(move |err| {
+            tx.send(err.kind()).unwrap();
+            err
+        });
+
+        let mut out = [0; 4];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        // Inner errors are passed through unchanged...
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "inner failure");
+        // ...while injected ones bypass the hook.
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(
+            partial_read.read(&mut out).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        let seen: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            seen,
+            vec![io::ErrorKind::BrokenPipe, io::ErrorKind::BrokenPipe]
+        );
+    }
+
+    #[test]
+    fn test_would_block_allowed_by_default() {
+        let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        let mut out = [0; 4];
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "use PartialAsyncRead or PartialAsyncWrite")]
+    fn test_forbid_would_block_sync() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+        partial_read.forbid_would_block_sync(true);
+        let mut out = [0; 4];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(
+            partial_read.read(&mut out).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        let _ = partial_read.read(&mut out);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "inner reader returned 3 byte(s) for a 2-byte buffer")]
+    fn test_over_reporting_inner() {
+        struct OverReport;
+
+        impl Read for OverReport {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                Ok(buf.len() + 1)
+            }
+        }
+
+        let mut partial_read = PartialRead::new(OverReport, vec![PartialOp::Limited(2)]);
+        let _ = partial_read.read(&mut [0; 8]);
+    }
+
+    #[test]
+    fn test_fail_flag() {
+        let down = Arc::new(AtomicBool::new(false));
+        let mut partial_read = PartialRead::with_fail_flag(
+            io::repeat(7),
+            iter::repeat(PartialOp::Limited(2)),
+            down.clone(),
+            io::ErrorKind::ConnectionReset,
+        );
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let reader = thread::spawn(move || {
+            let mut out = [0; 8];
+            loop {
+                match partial_read.read(&mut out) {
+                    Ok(n) => {
+                        assert_eq!(n, 2);
+                        // The receiver is gone after the first message.
+                        let _ = started_tx.send(());
+                    }
+                    Err(err) => return err.kind(),
+                }
+            }
+        });
+
+        started_rx.recv().unwrap();
+        down.store(true, Ordering::Relaxed);
+        assert_eq!(reader.join().unwrap(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn test_poison_on_error() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), ops);
+        partial_read.set_poison_on_error(true);
+        let mut out = vec![0; 8];
+
+        // Retryable errors leave the reader usable.
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+
+        let err = partial_read.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        // The script has an `Unlimited` next, but the reader stays poisoned.
+        for _ in 0..2 {
+            let err = partial_read.read(&mut out).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        }
+        assert_eq!(partial_read.ops_consumed(), 4);
+
+        partial_read.set_poison_on_error(false);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_chain() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            // Straddles the boundary.
+            PartialOp::Limited(3),
+            PartialOp::Limited(3),
+            PartialOp::Unlimited,
+        ];
+        let mut reader = chain(
+            Cursor::new(vec![1, 2, 3]),
+            Cursor::new(vec![4, 5, 6, 7]),
+            ops,
+        );
+        reader.set_recording(true);
+        let mut out = vec![0; 16];
+
+        assert_eq!(reader.read(&mut out).unwrap(), 2);
+        assert_eq!(reader.read(&mut out[2..]).unwrap(), 1);
+        // The first reader is exhausted, which this read notices before moving
+        // on to the second one.
+        assert_eq!(reader.read(&mut out[3..]).unwrap(), 3);
+        assert_eq!(reader.ops_consumed(), 3);
+        assert_eq!(reader.read(&mut out[6..]).unwrap(), 1);
+        assert_eq!(reader.read(&mut out[7..]).unwrap(), 0);
+        assert_eq!(&out[..7], &[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            reader.recorded(),
+            &[
+                (PartialOp::Limited(2), 2),
+                (PartialOp::Limited(3), 1),
+                (PartialOp::Limited(3), 3),
+                (PartialOp::Unlimited, 1),
+                (PartialOp::Unlimited, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_borrowed_inner() {
+        let data = [1, 2, 3, 4];
+        let mut partial_read = PartialRead::new(&data[..], vec![PartialOp::Limited(3)]);
+        let mut out = vec![0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.into_inner(), &[4]);
+
+        // Locked standard streams work as well. Don't actually read from stdin
+        // here, since that would block.
+        let stdin = io::stdin();
+        let partial_stdin = PartialRead::new(stdin.lock(), vec![PartialOp::Limited(1)]);
+        drop(partial_stdin);
+    }
+
+    #[test]
+    fn test_new_nonempty() {
+        let err = PartialRead::new_nonempty(Cursor::new(vec![1, 2]), Vec::<PartialOp>::new())
+            .unwrap_err();
+        assert_eq!(err, EmptyOps);
+
+        let mut partial_read =
+            PartialRead::new_nonempty(Cursor::new(vec![1, 2]), vec![PartialOp::Limited(1)])
+                .unwrap();
+        partial_read.expect_all_consumed();
+        let mut out = vec![0; 8];
+        // The first op is still applied.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+
+        // Infinite iterators work too.
+        assert!(PartialRead::new_nonempty(
+            Cursor::new(vec![1, 2]),
+            iter::repeat(PartialOp::Unlimited)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_buffer_too_large() {
+        let ops = vec![PartialOp::BufferTooLarge(4), PartialOp::Limited(1)];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![1, 2, 3, 4, 5, 6]), ops);
+        partial_read.set_recording(true);
+
+        // A small enough read goes through without consuming the op.
+        let mut small = [0; 3];
+        assert_eq!(partial_read.read(&mut small).unwrap(), 3);
+        assert_eq!(small, [1, 2, 3]);
+        assert_eq!(partial_read.ops_consumed(), 0);
+
+        let err = partial_read.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let too_large = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<BufferTooLargeError>())
+            .unwrap();
+        assert_eq!(too_large.max(), 4);
+        assert_eq!(partial_read.ops_consumed(), 1);
+        assert_eq!(
+            partial_read.recorded(),
+            &[(PartialOp::BufferTooLarge(4), 0)]
+        );
+
+        // The op was consumed by the error, so the next one applies.
+        let mut out = [0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 4);
+    }
+
+    #[test]
+    fn test_synthetic() {
+        let ops = iter::repeat(PartialOp::Limited(3));
+        let mut partial_read = synthetic(|i| (i % 251) as u8, 1000, ops);
+
+        let mut out = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = partial_read.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n <= 3);
+            out.extend_from_slice(&buf[..n]);
+        }
+        // 333 full reads, one of the last byte and one of EOF.
+        assert_eq!(partial_read.ops_consumed(), 335);
+        assert_eq!(partial_read.get_ref().remaining(), 0);
+        let expected: Vec<_> = (0..1000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(out, expected);
+    }
 }
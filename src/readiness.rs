@@ -0,0 +1,55 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains the state used by the async wrappers to wait for a
+//! blocked operation to be released explicitly.
+
+use futures::{Async, Stream};
+
+/// Tracks whether an async wrapper is blocked, and the stream of releases
+/// that unblocks it.
+pub(crate) struct Readiness {
+    releases: Box<dyn Stream<Item = (), Error = ()> + Send>,
+    blocked: bool,
+}
+
+impl Readiness {
+    pub(crate) fn new<S>(releases: S) -> Self
+    where
+        S: Stream<Item = (), Error = ()> + Send + 'static,
+    {
+        Readiness {
+            releases: Box::new(releases),
+            blocked: false,
+        }
+    }
+
+    /// Marks the wrapper as blocked until the next release.
+    #[inline]
+    pub(crate) fn block(&mut self) {
+        self.blocked = true;
+    }
+
+    /// Returns whether the wrapper is still blocked.
+    ///
+    /// If it is, the current task is registered to be notified once a release
+    /// arrives. This must be called from within a task.
+    pub(crate) fn poll_blocked(&mut self) -> bool {
+        if !self.blocked {
+            return false;
+        }
+        match self.releases.poll() {
+            Ok(Async::NotReady) => true,
+            // A release arrived, or no more can ever arrive because the
+            // stream ended or failed. Either way, stop blocking.
+            Ok(Async::Ready(_)) | Err(()) => {
+                self.blocked = false;
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains a seeded, reproducible generator of `PartialOp`s.
+
+use std::env;
+use std::io;
+use std::thread;
+
+use crate::PartialOp;
+
+/// The environment variable that [`PartialOpRng::from_env`] reads its seed
+/// from.
+///
+/// [`PartialOpRng::from_env`]: struct.PartialOpRng.html#method.from_env
+pub const SEED_ENV_VAR: &str = "PARTIAL_IO_SEED";
+
+/// The largest limit generated by default for `PartialOp::Limited` ops.
+const DEFAULT_MAX_LIMIT: usize = 64;
+
+/// The largest limit generated by the chaos profile for most `Limited` ops.
+const CHAOS_MAX_LIMIT: usize = 3;
+
+/// The largest limit generated by the chaos profile for the occasional
+/// larger `Limited` op.
+const CHAOS_MAX_LARGE_LIMIT: usize = 16;
+
+/// An infinite, reproducible sequence of random `PartialOp`s.
+///
+/// The same seed always produces the same sequence of ops, on every platform.
+/// By default, 10% of the generated ops are `Interrupted` errors, 10% are
+/// `WouldBlock` errors, and the rest are `Limited` with a limit between 1 and
+/// 64 bytes.
+///
+/// Unlike the [`quickcheck` types](quickcheck_types/index.html), this doesn't
+/// shrink failures, but it makes randomized tests cheap to reproduce: given the
+/// seed of a failing run, the exact same schedule can be replayed.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::PartialOpRng;
+///
+/// let first: Vec<_> = PartialOpRng::new(42).take(16).collect();
+/// let second: Vec<_> = PartialOpRng::new(42).take(16).collect();
+/// assert_eq!(first, second);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PartialOpRng {
+    seed: u64,
+    state: u64,
+    profile: Profile,
+    // The error kinds to draw errors from, with their weights, if not the
+    // profile's own.
+    error_kinds: Option<WeightedKinds>,
+    // Set for generators created with `from_env`, to print the seed if the
+    // thread panics.
+    seed_report: Option<SeedReport>,
+}
+
+/// Prints the seed of a generator created with `PartialOpRng::from_env` if it
+/// is dropped while the thread panics, e.g. because a test failed.
+#[derive(Clone, Debug)]
+struct SeedReport(u64);
+
+impl SeedReport {
+    fn message(&self) -> String {
+        format!(
+            "partial-io: seed at panic: {} (set {} to reproduce)",
+            self.0, SEED_ENV_VAR
+        )
+    }
+}
+
+impl Drop for SeedReport {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            eprintln!("{}", self.message());
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct WeightedKinds {
+    kinds: Vec<(io::ErrorKind, u32)>,
+    total: u64,
+}
+
+impl WeightedKinds {
+    fn pick(&self, mut n: u64) -> io::ErrorKind {
+        for &(kind, weight) in &self.kinds {
+            if n < weight as u64 {
+                return kind;
+            }
+            n -= weight as u64;
+        }
+        unreachable!("n is below the total weight")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Profile {
+    Default,
+    Chaos,
+}
+
+impl PartialOpRng {
+    /// Creates a new generator with the given seed.
+    pub fn new(seed: u64) -> Self {
+        PartialOpRng {
+            seed,
+            state: seed,
+            profile: Profile::Default,
+            error_kinds: None,
+            seed_report: None,
+        }
+    }
+
+    /// Returns a builder for a generator with more control over the ops it
+    /// generates.
+    ///
+    /// See [`PartialOpRngBuilder`](struct.PartialOpRngBuilder.html) for more.
+    pub fn builder() -> PartialOpRngBuilder {
+        PartialOpRngBuilder::default()
+    }
+
+    /// Creates a new generator with the given seed that breaks data up into
+    /// tiny pieces.
+    ///
+    /// This profile is tuned to stress reassembly logic: 85% of the generated
+    /// ops are `Limited` with a limit between 1 and 3 bytes, 5% are `Limited`
+    /// with a limit of up to 16 bytes, and 10% are `WouldBlock` errors. As with
+    /// `new`, the same seed always produces the same sequence, and `seed`
+    /// returns it for reproduction.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Cursor;
+    ///
+    /// use partial_io::{transfer_default, PartialOpRng, PartialRead, PartialWrite};
+    ///
+    /// let data: Vec<u8> = (0..=255).collect();
+    /// let mut reader = PartialRead::new(Cursor::new(data.clone()), PartialOpRng::chaos(1));
+    /// let mut writer = PartialWrite::new(Vec::new(), PartialOpRng::chaos(2));
+    ///
+    /// transfer_default(&mut reader, &mut writer).unwrap();
+    /// assert_eq!(writer.get_ref(), &data);
+    /// ```
+    pub fn chaos(seed: u64) -> Self {
+        PartialOpRng {
+            profile: Profile::Chaos,
+            ..Self::new(seed)
+        }
+    }
+
+    /// Creates a new generator with the seed from the `PARTIAL_IO_SEED`
+    /// environment variable, or `default_seed` if it isn't set.
+    ///
+    /// If the thread panics while the generator is alive, e.g. because a test
+    /// using a wrapper that draws from it failed, the effective seed is printed
+    /// to stderr so that it shows up in the output of the failing test. To
+    /// reproduce the failure, rerun the test with `PARTIAL_IO_SEED` set to
+    /// that seed. Nothing is printed otherwise; use `seed` to get the seed
+    /// directly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `PARTIAL_IO_SEED` is set but isn't a valid `u64`.
+    pub fn from_env(default_seed: u64) -> Self {
+        let mut rng = Self::from_env_value(env::var(SEED_ENV_VAR).ok(), default_seed);
+        rng.seed_report = Some(SeedReport(rng.seed));
+        rng
+    }
+
+    /// Creates a new generator with a seed derived from `name`, e.g. the name
+    /// of the test.
+    ///
+    /// This gives every test its own reproducible sequence of ops without
+    /// having to assign seeds by hand. The seed is the 64-bit FNV-1a hash of
+    /// the UTF-8 bytes of `name`. This algorithm is fixed, so the same name
+    /// produces the same seed on every platform and in every version of this
+    /// crate.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialOpRng;
+    ///
+    /// let rng = PartialOpRng::from_str("my_test");
+    /// assert_eq!(rng.seed(), PartialOpRng::from_str("my_test").seed());
+    /// assert_ne!(rng.seed(), PartialOpRng::from_str("my_other_test").seed());
+    /// ```
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Self {
+        Self::new(fnv1a(name.as_bytes()))
+    }
+
+    fn from_env_value(value: Option<String>, default_seed: u64) -> Self {
+        let seed = match value {
+            Some(value) => value.trim().parse().unwrap_or_else(|err| {
+                panic!("{} must be a u64, got {:?}: {}", SEED_ENV_VAR, value, err)
+            }),
+            None => default_seed,
+        };
+        Self::new(seed)
+    }
+
+    /// Returns the seed this generator was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the next pseudo-random number, using SplitMix64.
+    ///
+    /// This algorithm is fixed so that seeds stay reproducible across
+    /// platforms and versions of this crate.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo-random number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns an error drawn from the weighted error kinds, which must be set.
+    fn weighted_error(&mut self) -> PartialOp {
+        let total = self
+            .error_kinds
+            .as_ref()
+            .expect("error kinds are set")
+            .total;
+        let n = self.below(total);
+        PartialOp::Err(self.error_kinds.as_ref().unwrap().pick(n))
+    }
+}
+
+/// A builder for a [`PartialOpRng`].
+///
+/// This starts out generating the same ops as `PartialOpRng::new(0)`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{PartialOp, PartialOpRng};
+///
+/// let rng = PartialOpRng::builder()
+///     .seed(42)
+///     .error_kinds(&[(io::ErrorKind::BrokenPipe, 3), (io::ErrorKind::ConnectionReset, 1)])
+///     .build();
+/// assert!(rng.take(100).all(|op| match op {
+///     PartialOp::Err(kind) => {
+///         kind == io::ErrorKind::BrokenPipe || kind == io::ErrorKind::ConnectionReset
+///     }
+///     _ => true,
+/// }));
+/// ```
+///
+/// [`PartialOpRng`]: struct.PartialOpRng.html
+#[derive(Clone, Debug, Default)]
+pub struct PartialOpRngBuilder {
+    seed: u64,
+    error_kinds: Option<WeightedKinds>,
+}
+
+impl PartialOpRngBuilder {
+    /// Sets the seed of the generator.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Makes the generated errors be drawn from `kinds`, each with a
+    /// probability proportional to its weight.
+    ///
+    /// This replaces the default mix of `Interrupted` and `WouldBlock` errors,
+    /// e.g. with the failures a particular transport produces. Errors still
+    /// make up 20% of the generated ops. As with the rest of the sequence, the
+    /// same seed always produces the same errors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the weights add up to 0.
+    pub fn error_kinds(mut self, kinds: &[(io::ErrorKind, u32)]) -> Self {
+        let total = kinds.iter().map(|&(_, weight)| weight as u64).sum();
+        assert!(total > 0, "error_kinds: the weights must not add up to 0");
+        self.error_kinds = Some(WeightedKinds {
+            kinds: kinds.to_vec(),
+            total,
+        });
+        self
+    }
+
+    /// Creates the generator.
+    pub fn build(self) -> PartialOpRng {
+        PartialOpRng {
+            error_kinds: self.error_kinds,
+            ..PartialOpRng::new(self.seed)
+        }
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+impl Iterator for PartialOpRng {
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<PartialOp> {
+        let op = match self.profile {
+            Profile::Default => match self.below(10) {
+                0 | 1 if self.error_kinds.is_some() => self.weighted_error(),
+                0 => PartialOp::Err(io::ErrorKind::Interrupted),
+                1 => PartialOp::Err(io::ErrorKind::WouldBlock),
+                _ => PartialOp::Limited(1 + self.below(DEFAULT_MAX_LIMIT as u64) as usize),
+            },
+            Profile::Chaos => match self.below(20) {
+                0 | 1 => PartialOp::Err(io::ErrorKind::WouldBlock),
+                2 => PartialOp::Limited(1 + self.below(CHAOS_MAX_LARGE_LIMIT as u64) as usize),
+                _ => PartialOp::Limited(1 + self.below(CHAOS_MAX_LIMIT as u64) as usize),
+            },
+        };
+        Some(op)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{transfer_default, PartialRead, PartialWrite};
+
+    #[test]
+    fn test_reproducible() {
+        let first: Vec<_> = PartialOpRng::new(7).take(100).collect();
+        let second: Vec<_> = PartialOpRng::new(7).take(100).collect();
+        let other: Vec<_> = PartialOpRng::new(8).take(100).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(first.iter().all(|op| match op {
+            PartialOp::Limited(n) => (1..=DEFAULT_MAX_LIMIT).contains(n),
+            PartialOp::Err(_) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_chaos_round_trip() {
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let rng = PartialOpRng::chaos(99);
+        assert_eq!(rng.seed(), 99);
+        assert!(rng.clone().take(1000).all(|op| match op {
+            PartialOp::Limited(n) => (1..=CHAOS_MAX_LARGE_LIMIT).contains(&n),
+            PartialOp::Err(kind) => kind == io::ErrorKind::WouldBlock,
+            _ => false,
+        }));
+
+        let mut reader = PartialRead::new(Cursor::new(data.clone()), rng);
+        let mut writer = PartialWrite::new(Vec::new(), PartialOpRng::chaos(100));
+        transfer_default(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer.into_inner(), data);
+    }
+
+    #[test]
+    fn test_weighted_error_kinds() {
+        let build = || {
+            PartialOpRng::builder()
+                .seed(3)
+                .error_kinds(&[
+                    (io::ErrorKind::BrokenPipe, 3),
+                    (io::ErrorKind::ConnectionReset, 1),
+                    (io::ErrorKind::Other, 0),
+                ])
+                .build()
+        };
+        let ops: Vec<_> = build().take(20_000).collect();
+        assert_eq!(ops, build().take(20_000).collect::<Vec<_>>());
+
+        let count = |kind| ops.iter().filter(|op| **op == PartialOp::Err(kind)).count();
+        let broken_pipe = count(io::ErrorKind::BrokenPipe);
+        let connection_reset = count(io::ErrorKind::ConnectionReset);
+        assert_eq!(count(io::ErrorKind::Other), 0);
+        // About 20% of the ops are errors, 3 out of 4 of them broken pipes.
+        let errors = broken_pipe + connection_reset;
+        assert!((3_600..=4_400).contains(&errors), "{} errors", errors);
+        let ratio = broken_pipe as f64 / errors as f64;
+        assert!((0.7..=0.8).contains(&ratio), "{} broken pipes", ratio);
+    }
+
+    #[test]
+    #[should_panic(expected = "error_kinds: the weights must not add up to 0")]
+    fn test_weighted_error_kinds_zero() {
+        PartialOpRng::builder().error_kinds(&[(io::ErrorKind::Other, 0)]);
+    }
+
+    #[test]
+    fn test_from_str() {
+        // These are the published FNV-1a test vectors, which must never change.
+        assert_eq!(PartialOpRng::from_str("").seed(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(PartialOpRng::from_str("a").seed(), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            PartialOpRng::from_str("foobar").seed(),
+            0x8594_4171_f739_67e8
+        );
+    }
+
+    #[test]
+    fn test_from_env_value() {
+        assert_eq!(PartialOpRng::from_env_value(None, 5).seed(), 5);
+        assert_eq!(
+            PartialOpRng::from_env_value(Some("1234".to_string()), 5).seed(),
+            1234
+        );
+    }
+
+    #[test]
+    fn test_seed_report() {
+        assert_eq!(
+            SeedReport(1234).message(),
+            "partial-io: seed at panic: 1234 (set PARTIAL_IO_SEED to reproduce)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "PARTIAL_IO_SEED must be a u64")]
+    fn test_from_env_value_invalid() {
+        PartialOpRng::from_env_value(Some("abc".to_string()), 5);
+    }
+}
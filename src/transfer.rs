@@ -0,0 +1,408 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains a helper that copies data from a reader to a writer,
+//! driving partial and interrupted operations to completion.
+
+use std::error;
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+
+use crate::{PartialOp, PartialRead, PartialWrite};
+
+/// The default buffer size used by [`transfer_default`].
+///
+/// [`transfer_default`]: fn.transfer_default.html
+pub const DEFAULT_TRANSFER_BUF_SIZE: usize = 8 * 1024;
+
+/// Statistics about a completed [`transfer`].
+///
+/// [`transfer`]: fn.transfer.html
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TransferStats {
+    /// The total number of bytes copied.
+    pub bytes: u64,
+    /// The number of calls to `read`, including ones that failed or returned EOF.
+    pub read_calls: usize,
+    /// The number of calls to `write`, including ones that failed.
+    pub write_calls: usize,
+    /// The number of `Interrupted` or `WouldBlock` errors that were retried.
+    pub retries: usize,
+}
+
+/// Copies all data from `reader` to `writer` through an `N`-byte buffer, then
+/// flushes `writer`.
+///
+/// Short reads and writes are driven to completion, and `Interrupted` and
+/// `WouldBlock` errors from either side are retried. Any other error is
+/// returned immediately.
+///
+/// Choosing the buffer size at compile time makes chunking deterministic: for
+/// example, `transfer::<1, _, _>` copies one byte per call at most, which
+/// combined with small `PartialOp::Limited` ops fragments the data as much as
+/// possible.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use partial_io::{transfer, PartialOp, PartialRead, PartialWrite};
+///
+/// let data = b"Hello, world!".to_vec();
+/// let mut reader = PartialRead::new(Cursor::new(data.clone()), vec![PartialOp::Limited(2)]);
+/// let mut writer = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(3)]);
+///
+/// let stats = transfer::<4, _, _>(&mut reader, &mut writer).unwrap();
+/// assert_eq!(stats.bytes, 13);
+/// assert_eq!(writer.get_ref(), &data);
+/// ```
+pub fn transfer<const N: usize, R, W>(reader: &mut R, writer: &mut W) -> io::Result<TransferStats>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut stats = TransferStats::default();
+    let mut buf = [0u8; N];
+
+    loop {
+        stats.read_calls += 1;
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref err) if is_retryable(err) => {
+                stats.retries += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut written = 0;
+        while written < len {
+            stats.write_calls += 1;
+            match writer.write(&buf[written..len]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => written += n,
+                Err(ref err) if is_retryable(err) => stats.retries += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        stats.bytes += len as u64;
+    }
+
+    loop {
+        match writer.flush() {
+            Ok(()) => return Ok(stats),
+            Err(ref err) if is_retryable(err) => stats.retries += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Copies all data from `reader` to `writer` using a buffer of
+/// [`DEFAULT_TRANSFER_BUF_SIZE`] bytes.
+///
+/// See [`transfer`] for more.
+///
+/// [`DEFAULT_TRANSFER_BUF_SIZE`]: constant.DEFAULT_TRANSFER_BUF_SIZE.html
+/// [`transfer`]: fn.transfer.html
+pub fn transfer_default<R, W>(reader: &mut R, writer: &mut W) -> io::Result<TransferStats>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    transfer::<DEFAULT_TRANSFER_BUF_SIZE, R, W>(reader, writer)
+}
+
+/// Asserts that `data` comes through a round trip intact under each of two
+/// scripts of `PartialOp`s.
+///
+/// For each script, `data` is [`transfer`]red from a `PartialRead` to a
+/// `PartialWrite` that both apply the script, and the bytes that end up in the
+/// writer are compared to `data`. This checks the core invariant that partial
+/// operations never corrupt data, e.g. to make sure a rewritten op generator
+/// still produces a schedule that's equivalent to the old one, even if the
+/// calls it results in differ.
+///
+/// # Panics
+///
+/// Panics if the transfer fails with an error other than `Interrupted` or
+/// `WouldBlock` under either script, or if the bytes written differ from
+/// `data`, e.g. because a `PartialOp::Eof` cut the transfer short.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{assert_equivalent, PartialOp};
+///
+/// let fine = vec![PartialOp::Limited(1); 8];
+/// let coarse = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Unlimited];
+/// assert_equivalent(b"Hello, world!", &fine, &coarse);
+/// ```
+///
+/// [`transfer`]: fn.transfer.html
+pub fn assert_equivalent(data: &[u8], script_a: &[PartialOp], script_b: &[PartialOp]) {
+    for &(name, script) in &[("first", script_a), ("second", script_b)] {
+        let mut reader = PartialRead::new(Cursor::new(data.to_vec()), script.to_vec());
+        let mut writer = PartialWrite::new(Vec::new(), script.to_vec());
+        if let Err(err) = transfer_default(&mut reader, &mut writer) {
+            panic!(
+                "assert_equivalent: transfer under the {} script failed: {}",
+                name, err
+            );
+        }
+        assert!(
+            writer.get_ref()[..] == *data,
+            "assert_equivalent: the {} script produced {} byte(s) that differ from the {} byte(s) of data",
+            name,
+            writer.get_ref().len(),
+            data.len()
+        );
+    }
+}
+
+/// The reason a script failed [`validate_script`].
+///
+/// [`validate_script`]: fn.validate_script.html
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScriptError {
+    /// Writing the payload failed with an error that isn't retried.
+    Write(io::Error),
+    /// Reading the payload back failed with an error that isn't retried.
+    Read(io::Error),
+    /// The payload came back different from how it went in.
+    Mismatch {
+        /// The offset of the first byte that differs, or the length of the
+        /// shorter side if one is a prefix of the other.
+        offset: usize,
+        /// The length of the payload.
+        expected_len: usize,
+        /// The number of bytes that came back.
+        actual_len: usize,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Write(err) => write!(f, "writing the payload failed: {}", err),
+            ScriptError::Read(err) => write!(f, "reading the payload back failed: {}", err),
+            ScriptError::Mismatch {
+                offset,
+                expected_len,
+                actual_len,
+            } => write!(
+                f,
+                "the payload diverged at offset {}: expected {} byte(s), got {}",
+                offset, expected_len, actual_len
+            ),
+        }
+    }
+}
+
+impl error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ScriptError::Write(err) | ScriptError::Read(err) => Some(err),
+            ScriptError::Mismatch { .. } => None,
+        }
+    }
+}
+
+/// Checks that `payload` comes through a round trip intact under `script`.
+///
+/// `payload` is [`transfer`]red into a `PartialWrite` that applies `script`,
+/// and what ends up in the writer is then transferred out of a `PartialRead`
+/// that applies `script` again. This is a sanity check for a hand-written
+/// script that many tests rely on: unlike [`assert_equivalent`], it checks a
+/// single script and returns an error describing where things went wrong
+/// rather than panicking.
+///
+/// # Errors
+///
+/// Returns `ScriptError::Write` or `ScriptError::Read` if either half fails
+/// with an error other than `Interrupted` or `WouldBlock`, e.g. because of a
+/// `PartialOp::Eof` op on the write side. Returns `ScriptError::Mismatch` with
+/// the offset of the first difference if the bytes read back differ from
+/// `payload`, e.g. because of a `PartialOp::DropTail` op.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::{validate_script, PartialOp};
+///
+/// let script = vec![
+///     PartialOp::Limited(3),
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Limited(5),
+/// ];
+/// validate_script(b"Hello, world!", &script).unwrap();
+///
+/// let err = validate_script(b"Hello, world!", &[PartialOp::DropTail(5)]).unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "the payload diverged at offset 5: expected 13 byte(s), got 5",
+/// );
+/// ```
+///
+/// [`transfer`]: fn.transfer.html
+/// [`assert_equivalent`]: fn.assert_equivalent.html
+pub fn validate_script(payload: &[u8], script: &[PartialOp]) -> Result<(), ScriptError> {
+    let mut writer = PartialWrite::new(Vec::new(), script.to_vec());
+    transfer_default(&mut Cursor::new(payload), &mut writer).map_err(ScriptError::Write)?;
+
+    let mut reader = PartialRead::new(Cursor::new(writer.into_inner()), script.to_vec());
+    let mut out = Vec::with_capacity(payload.len());
+    transfer_default(&mut reader, &mut out).map_err(ScriptError::Read)?;
+
+    if out[..] == *payload {
+        return Ok(());
+    }
+    let offset = payload
+        .iter()
+        .zip(&out)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or_else(|| payload.len().min(out.len()));
+    Err(ScriptError::Mismatch {
+        offset,
+        expected_len: payload.len(),
+        actual_len: out.len(),
+    })
+}
+
+#[inline]
+fn is_retryable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::iter;
+
+    fn run<const N: usize>(data: &[u8]) -> (Vec<u8>, TransferStats) {
+        let read_ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(5),
+        ];
+        let write_ops = iter::repeat(PartialOp::Limited(7))
+            .take(4)
+            .chain(iter::once(PartialOp::Err(io::ErrorKind::WouldBlock)));
+        let mut reader = PartialRead::new(Cursor::new(data.to_vec()), read_ops);
+        let mut writer = PartialWrite::new(Vec::new(), write_ops);
+
+        let stats = transfer::<N, _, _>(&mut reader, &mut writer).unwrap();
+        (writer.into_inner(), stats)
+    }
+
+    #[test]
+    fn test_transfer_buffer_sizes() {
+        let data: Vec<u8> = (0..100).collect();
+        let (small_out, small_stats) = run::<1>(&data);
+        let (large_out, large_stats) = run::<4096>(&data);
+
+        assert_eq!(small_out, data);
+        assert_eq!(large_out, data);
+        assert_eq!(small_stats.bytes, 100);
+        assert_eq!(large_stats.bytes, 100);
+
+        // One byte at a time: 100 successful reads, one interrupted read and
+        // one read returning EOF, and one write per byte plus a blocked one.
+        assert_eq!(small_stats.read_calls, 102);
+        assert_eq!(small_stats.write_calls, 101);
+        assert_eq!(small_stats.retries, 2);
+        assert!(large_stats.read_calls < small_stats.read_calls);
+        assert!(large_stats.write_calls < small_stats.write_calls);
+    }
+
+    #[test]
+    fn test_transfer_default() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = Cursor::new(data.clone());
+        let mut writer = Vec::new();
+
+        let stats = transfer_default(&mut reader, &mut writer).unwrap();
+        assert_eq!(writer, data);
+        assert_eq!(stats.read_calls, 2);
+        assert_eq!(stats.write_calls, 1);
+    }
+
+    #[test]
+    fn test_assert_equivalent() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let fine: Vec<_> = iter::repeat(PartialOp::Limited(1))
+            .take(50)
+            .chain(iter::repeat(PartialOp::Err(io::ErrorKind::Interrupted)).take(3))
+            .chain(iter::repeat(PartialOp::Limited(2)).take(50))
+            .collect();
+        let coarse = vec![
+            PartialOp::Limited(512),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Unlimited,
+        ];
+        assert_equivalent(&data, &fine, &coarse);
+    }
+
+    #[test]
+    #[should_panic(expected = "the second script produced 3 byte(s)")]
+    fn test_assert_equivalent_truncated() {
+        let data: Vec<u8> = (0..10).collect();
+        assert_equivalent(
+            &data,
+            &[PartialOp::Unlimited],
+            &[PartialOp::Limited(3), PartialOp::Eof],
+        );
+    }
+
+    #[test]
+    fn test_validate_script() {
+        let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let script: Vec<_> = iter::repeat(PartialOp::Limited(7))
+            .take(20)
+            .chain(iter::once(PartialOp::Err(io::ErrorKind::WouldBlock)))
+            .chain(iter::once(PartialOp::Delimiter(0)))
+            .collect();
+        validate_script(&payload, &script).unwrap();
+        validate_script(&[], &script).unwrap();
+    }
+
+    #[test]
+    fn test_validate_script_errors() {
+        let payload: Vec<u8> = (0..10).collect();
+        let script = [PartialOp::Limited(2), PartialOp::DropTail(3)];
+        match validate_script(&payload, &script).unwrap_err() {
+            ScriptError::Mismatch {
+                offset,
+                expected_len,
+                actual_len,
+            } => assert_eq!((offset, expected_len, actual_len), (5, 10, 5)),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let err = validate_script(&payload, &[PartialOp::Eof]).unwrap_err();
+        assert!(matches!(&err, ScriptError::Write(err) if err.kind() == io::ErrorKind::WriteZero));
+        assert_eq!(
+            err.to_string(),
+            "writing the payload failed: failed to write whole buffer"
+        );
+    }
+}
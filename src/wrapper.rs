@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! This module contains a trait implemented by all of the wrappers, for code
+//! that is generic over which one it deals with.
+
+#[cfg(feature = "tokio")]
+use crate::{PartialAsyncRead, PartialAsyncWrite};
+use crate::{PartialOp, PartialRead, PartialWrite};
+
+/// The interface shared by [`PartialRead`], [`PartialWrite`],
+/// [`PartialAsyncRead`] and [`PartialAsyncWrite`].
+///
+/// This allows test helpers to manipulate the ops of any wrapper, sync or
+/// async, reader or writer. Each method behaves like the wrapper's inherent
+/// method of the same name.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read, Write};
+///
+/// use partial_io::{PartialIo, PartialOp, PartialRead, PartialWrite};
+///
+/// fn limit_to_one(wrapper: &mut impl PartialIo) {
+///     wrapper.reset_ops(vec![PartialOp::Limited(1)]);
+/// }
+///
+/// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), Vec::<PartialOp>::new());
+/// let mut writer = PartialWrite::new(Vec::new(), Vec::<PartialOp>::new());
+/// limit_to_one(&mut reader);
+/// limit_to_one(&mut writer);
+/// assert_eq!(reader.read(&mut [0; 8]).unwrap(), 1);
+/// assert_eq!(writer.write(&[1, 2, 3]).unwrap(), 1);
+/// ```
+///
+/// [`PartialRead`]: struct.PartialRead.html
+/// [`PartialWrite`]: struct.PartialWrite.html
+/// [`PartialAsyncRead`]: struct.PartialAsyncRead.html
+/// [`PartialAsyncWrite`]: struct.PartialAsyncWrite.html
+pub trait PartialIo {
+    /// The type of the wrapped reader or writer.
+    type Inner;
+
+    /// Sets the `PartialOp`s for this wrapper.
+    fn set_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send;
+
+    /// Sets the `PartialOp`s for this wrapper, and starts counting consumed
+    /// ops over from zero.
+    fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send;
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    fn ops_consumed(&self) -> usize;
+
+    /// Acquires a reference to the underlying reader or writer.
+    fn get_ref(&self) -> &Self::Inner;
+
+    /// Acquires a mutable reference to the underlying reader or writer.
+    fn get_mut(&mut self) -> &mut Self::Inner;
+
+    /// Consumes this wrapper, returning the underlying reader or writer.
+    fn into_inner(self) -> Self::Inner
+    where
+        Self: Sized;
+}
+
+// The inherent methods take precedence over the trait methods of the same
+// name, so these forward to them.
+macro_rules! impl_partial_io {
+    ($wrapper:ident, $bound:path) => {
+        impl<T> PartialIo for $wrapper<T>
+        where
+            T: $bound,
+        {
+            type Inner = T;
+
+            fn set_ops<I>(&mut self, iter: I) -> &mut Self
+            where
+                I: IntoIterator + 'static,
+                I::Item: Into<PartialOp>,
+                I::IntoIter: Send,
+            {
+                $wrapper::set_ops(self, iter)
+            }
+
+            fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+            where
+                I: IntoIterator + 'static,
+                I::Item: Into<PartialOp>,
+                I::IntoIter: Send,
+            {
+                $wrapper::reset_ops(self, iter)
+            }
+
+            fn ops_consumed(&self) -> usize {
+                $wrapper::ops_consumed(self)
+            }
+
+            fn get_ref(&self) -> &T {
+                $wrapper::get_ref(self)
+            }
+
+            fn get_mut(&mut self) -> &mut T {
+                $wrapper::get_mut(self)
+            }
+
+            fn into_inner(self) -> T {
+                $wrapper::into_inner(self)
+            }
+        }
+    };
+}
+
+impl_partial_io!(PartialRead, std::io::Read);
+impl_partial_io!(PartialWrite, std::io::Write);
+#[cfg(feature = "tokio")]
+impl_partial_io!(PartialAsyncRead, tokio_io::AsyncRead);
+#[cfg(feature = "tokio")]
+impl_partial_io!(PartialAsyncWrite, tokio_io::AsyncWrite);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{self, Cursor, Read, Write};
+
+    /// Runs `step` under two scripts, resetting the ops in between.
+    fn run_twice<P, F>(wrapper: &mut P, mut step: F) -> (Vec<usize>, Vec<usize>)
+    where
+        P: PartialIo,
+        F: FnMut(&mut P) -> io::Result<usize>,
+    {
+        let mut run = |wrapper: &mut P, ops: Vec<PartialOp>| {
+            wrapper.reset_ops(ops);
+            let sizes: Vec<_> = (0..3).map(|_| step(wrapper).unwrap()).collect();
+            assert_eq!(wrapper.ops_consumed(), 3);
+            sizes
+        };
+        let first = run(wrapper, vec![PartialOp::Limited(1); 3]);
+        let second = run(wrapper, vec![PartialOp::Limited(2); 3]);
+        (first, second)
+    }
+
+    #[test]
+    fn test_generic_reset() {
+        let mut reader = PartialRead::new(Cursor::new(vec![0; 16]), Vec::<PartialOp>::new());
+        let sizes = run_twice(&mut reader, |reader| reader.read(&mut [0; 8]));
+        assert_eq!(sizes, (vec![1, 1, 1], vec![2, 2, 2]));
+        assert_eq!(PartialIo::get_ref(&reader).position(), 9);
+
+        let mut writer = PartialWrite::new(Vec::new(), Vec::<PartialOp>::new());
+        let sizes = run_twice(&mut writer, |writer| writer.write(b"abcd"));
+        assert_eq!(sizes, (vec![1, 1, 1], vec![2, 2, 2]));
+        PartialIo::get_mut(&mut writer).push(b'!');
+        assert_eq!(PartialIo::into_inner(writer), b"aaaababab!");
+    }
+}
@@ -8,11 +8,19 @@
 //! This module contains a writer wrapper that breaks writes up according to a
 //! provided iterator.
 
+use std::any;
 use std::cmp;
 use std::fmt;
-use std::io::{self, Read, Write};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
-use crate::{make_ops, PartialOp};
+use crate::observer::OpObserver;
+use crate::ops::{
+    buffer_too_large, delimited_len, first_cap, injected_error, EmptyOps, Operation, Ops,
+    PartialIoStats,
+};
+use crate::PartialOp;
 
 /// A writer wrapper that breaks inner `Write` instances up according to the
 /// provided iterator.
@@ -35,7 +43,17 @@ use crate::{make_ops, PartialOp};
 /// ```
 pub struct PartialWrite<W> {
     inner: W,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops,
+    bytes_transferred: u64,
+    watermark: Option<(u64, io::ErrorKind)>,
+    atomic_writes: bool,
+    sink: Option<SinkCapacity>,
+}
+
+/// The simulated room left in the sink, for `PartialWrite::with_sink_capacity`.
+struct SinkCapacity {
+    remaining: usize,
+    refill: Box<dyn FnMut() -> usize + Send>,
 }
 
 impl<W> PartialWrite<W>
@@ -45,27 +63,277 @@ where
     /// Creates a new `PartialWrite` wrapper over the writer with the specified `PartialOp`s.
     pub fn new<I>(inner: W, iter: I) -> Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Self::from_ops(inner, Ops::new(iter))
+    }
+
+    /// Creates a new `PartialWrite` wrapper like `new`, but fails if `iter` is
+    /// empty.
+    ///
+    /// See [`PartialRead::new_nonempty`](struct.PartialRead.html#method.new_nonempty)
+    /// for more.
+    pub fn new_nonempty<I>(inner: W, iter: I) -> Result<Self, EmptyOps>
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        Ok(Self::from_ops(inner, Ops::new_nonempty(iter)?))
+    }
+
+    /// Creates a new `PartialWrite` wrapper whose writes and flushes fail with
+    /// `kind` while `flag` is set.
+    ///
+    /// See [`PartialRead::with_fail_flag`](struct.PartialRead.html#method.with_fail_flag)
+    /// for more.
+    pub fn with_fail_flag<I>(inner: W, iter: I, flag: Arc<AtomicBool>, kind: io::ErrorKind) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
+        let mut writer = Self::new(inner, iter);
+        writer.ops.set_fail_flag(flag, kind);
+        writer
+    }
+
+    /// Creates a new `PartialWrite` wrapper that replays a trace of write
+    /// results.
+    ///
+    /// Each result is turned into an op with
+    /// [`PartialOp::from_result`](enum.PartialOp.html#method.from_result):
+    /// `Ok(0)` becomes an injected end of file, other successes become limits
+    /// and errors are injected with the same kind.
+    pub fn from_results<I>(inner: W, results: I) -> Self
+    where
+        I: IntoIterator<Item = io::Result<usize>>,
+    {
+        let ops: Vec<_> = results
+            .into_iter()
+            .map(|res| PartialOp::from_result(&res))
+            .collect();
+        Self::new(inner, ops)
+    }
+
+    /// Creates a new `PartialWrite` wrapper over a sink with limited room.
+    ///
+    /// The sink starts out with room for `initial` bytes. Every write first
+    /// adds the number of bytes `refill` returns, which models the sink
+    /// draining in the background, and is then capped at the room left, on top
+    /// of what the op allows. The bytes written use the room up. A write that
+    /// finds no room at all fails with `WouldBlock` without consuming an op.
+    ///
+    /// Unlike the write watermark, the room comes back over time, and unlike
+    /// ops, it depends on how much was actually written.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// // The sink drains 2 bytes between writes.
+    /// let mut writer = PartialWrite::with_sink_capacity(Vec::new(), vec![PartialOp::Unlimited], 4, || 2);
+    /// assert_eq!(writer.write(b"hello world").unwrap(), 6);
+    /// assert_eq!(writer.write(b" world").unwrap(), 2);
+    /// ```
+    pub fn with_sink_capacity<I, F>(inner: W, iter: I, initial: usize, refill: F) -> Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+        F: FnMut() -> usize + Send + 'static,
+    {
+        let mut writer = Self::new(inner, iter);
+        writer.sink = Some(SinkCapacity {
+            remaining: initial,
+            refill: Box::new(refill),
+        });
+        writer
+    }
+
+    fn from_ops(inner: W, ops: Ops) -> Self {
         PartialWrite {
             inner,
-            // Use fuse here so that we don't keep calling the inner iterator
-            // once it's returned None.
-            ops: make_ops(iter),
+            ops,
+            bytes_transferred: 0,
+            watermark: None,
+            atomic_writes: false,
+            sink: None,
         }
     }
 
     /// Sets the `PartialOp`s for this writer.
     pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
     where
-        I: IntoIterator<Item = PartialOp> + 'static,
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.ops.set(iter);
+        self
+    }
+
+    /// Sets the `PartialOp`s for this writer, and starts counting consumed ops
+    /// over from zero.
+    ///
+    /// See [`PartialRead::reset_ops`](struct.PartialRead.html#method.reset_ops)
+    /// for more.
+    pub fn reset_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator + 'static,
+        I::Item: Into<PartialOp>,
+        I::IntoIter: Send,
+    {
+        self.set_ops(iter);
+        self.ops.reset_counts();
+        self
+    }
+
+    /// Sets a cumulative limit on the number of bytes that can be written.
+    ///
+    /// Once `bytes` bytes have been written in total, every subsequent write
+    /// fails with an error of the given kind until the watermark is raised or
+    /// cleared. A write that would cross the watermark is shortened to end
+    /// exactly at it. This can be used to test disk-full scenarios.
+    ///
+    /// The watermark is checked before consulting the `PartialOp`s, so writes
+    /// that fail because of it don't consume an op.
+    pub fn set_write_watermark(&mut self, bytes: u64, kind: io::ErrorKind) -> &mut Self {
+        self.watermark = Some((bytes, kind));
+        self
+    }
+
+    /// Clears the watermark set by `set_write_watermark`.
+    pub fn clear_write_watermark(&mut self) -> &mut Self {
+        self.watermark = None;
+        self
+    }
+
+    /// Sets whether writes are all-or-nothing.
+    ///
+    /// With atomic writes, a `PartialOp::Limited` or `PartialOp::LimitedThenErr`
+    /// op smaller than the buffer fails the write with a `WouldBlock` error
+    /// instead of writing a prefix of it, modeling a socket that rejects
+    /// oversized datagrams. A write that would cross the watermark set by
+    /// `set_write_watermark` fails with the watermark's error instead of being
+    /// shortened.
+    ///
+    /// This only affects limits imposed by this wrapper. Short writes performed
+    /// by the inner writer itself are still passed through.
+    pub fn set_atomic_writes(&mut self, atomic: bool) -> &mut Self {
+        self.atomic_writes = atomic;
+        self
+    }
+
+    /// Returns the total number of bytes successfully written through this wrapper.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.bytes_transferred
+    }
+
+    /// Adds an observer to notify about every op this writer applies.
+    ///
+    /// Any number of observers can be added, and they coexist with recording.
+    /// See [`OpObserver`] for more.
+    ///
+    /// [`OpObserver`]: trait.OpObserver.html
+    pub fn add_observer(&mut self, observer: impl OpObserver + 'static) -> &mut Self {
+        self.ops.add_observer(Box::new(observer));
         self
     }
 
+    /// Skips up to `n` ops without performing any IO, returning how many were
+    /// actually skipped.
+    ///
+    /// See [`PartialRead::skip_ops`](struct.PartialRead.html#method.skip_ops)
+    /// for more.
+    pub fn skip_ops(&mut self, n: usize) -> usize {
+        self.ops.skip_ops(n)
+    }
+
+    /// Returns the number of `PartialOp`s consumed so far, including skipped
+    /// ones.
+    pub fn ops_consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns counts of the `PartialOp`s applied so far, by kind.
+    ///
+    /// See [`PartialIoStats`](struct.PartialIoStats.html) for more.
+    pub fn stats(&self) -> PartialIoStats {
+        self.ops.stats()
+    }
+
+    /// Panics when this writer is dropped if any of its `PartialOp`s were
+    /// never consumed.
+    ///
+    /// See [`PartialRead::expect_all_consumed`](struct.PartialRead.html#method.expect_all_consumed)
+    /// for more.
+    pub fn expect_all_consumed(&mut self) -> &mut Self {
+        self.ops.expect_all_consumed();
+        self
+    }
+
+    /// Sets whether applying a `PartialOp::Err(WouldBlock)` op panics.
+    ///
+    /// With this set, such an op panics with a message pointing to
+    /// `PartialAsyncWrite` instead of failing a write or flush.
+    ///
+    /// See [`PartialRead::forbid_would_block_sync`](struct.PartialRead.html#method.forbid_would_block_sync)
+    /// for more.
+    pub fn forbid_would_block_sync(&mut self, forbid: bool) -> &mut Self {
+        self.ops.forbid_would_block(forbid);
+        self
+    }
+
+    /// Sets whether a fatal injected error poisons this writer.
+    ///
+    /// Once a `PartialOp::Err` op with a kind other than `WouldBlock` or
+    /// `Interrupted` has fired, every later write or flush fails with
+    /// `ErrorKind::NotConnected` without consuming an op.
+    ///
+    /// See [`PartialRead::set_poison_on_error`](struct.PartialRead.html#method.set_poison_on_error)
+    /// for more.
+    pub fn set_poison_on_error(&mut self, poison: bool) -> &mut Self {
+        self.ops.set_poison_on_error(poison);
+        self
+    }
+
+    /// Sets whether to record the `PartialOp`s applied by this writer.
+    ///
+    /// See [`PartialRead::set_recording`](struct.PartialRead.html#method.set_recording)
+    /// for more.
+    pub fn set_recording(&mut self, record: bool) -> &mut Self {
+        self.ops.set_recording(record);
+        self
+    }
+
+    /// Returns the ops recorded so far, along with the number of bytes each
+    /// transferred.
+    pub fn recorded(&self) -> &[(PartialOp, usize)] {
+        self.ops.recorded()
+    }
+
+    /// Removes the ops recorded so far from the log, yielding them in order.
+    ///
+    /// See [`PartialRead::drain_events`](struct.PartialRead.html#method.drain_events)
+    /// for more.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = (PartialOp, usize)> + '_ {
+        self.ops.drain_recorded()
+    }
+
+    /// Writes the recorded ops to `w`, one per line.
+    ///
+    /// See [`PartialRead::dump_recorded`](struct.PartialRead.html#method.dump_recorded)
+    /// for the format.
+    pub fn dump_recorded(&self, w: &mut impl Write) -> io::Result<()> {
+        self.ops.dump_recorded(w)
+    }
+
     /// Acquires a reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         &self.inner
@@ -80,6 +348,69 @@ where
     pub fn into_inner(self) -> W {
         self.inner
     }
+
+    /// Returns the bytes written to the underlying writer so far.
+    ///
+    /// For a `Cursor`, this is the full underlying buffer, not just the part
+    /// before the current position. See [`WrittenBytes`] for more.
+    ///
+    /// [`WrittenBytes`]: trait.WrittenBytes.html
+    pub fn written(&self) -> &[u8]
+    where
+        W: WrittenBytes,
+    {
+        self.inner.written_bytes()
+    }
+}
+
+/// Writes the first `n` bytes of `buf` to `inner`, but reports all of `buf` as
+/// written.
+pub(crate) fn write_drop_tail<W>(inner: &mut W, buf: &[u8], n: usize) -> io::Result<usize>
+where
+    W: Write + ?Sized,
+{
+    let len = cmp::min(n, buf.len());
+    inner.write_all(&buf[..len])?;
+    Ok(buf.len())
+}
+
+impl<W> PartialWrite<W>
+where
+    W: Write,
+{
+    fn account_written(&mut self, res: &io::Result<usize>) {
+        if let Ok(n) = *res {
+            self.bytes_transferred += n as u64;
+            if let Some(sink) = &mut self.sink {
+                sink.remaining = sink.remaining.saturating_sub(n);
+            }
+        }
+    }
+
+    fn write_limited(&mut self, buf: &[u8], n: usize) -> io::Result<usize> {
+        if self.atomic_writes && n < buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "write larger than limit, generated by partial-io",
+            ));
+        }
+        let len = cmp::min(n, buf.len());
+        self.inner.write(&buf[..len])
+    }
+}
+
+impl<W> PartialWrite<W>
+where
+    W: Seek,
+{
+    /// Returns the current position of the inner writer, without consuming an
+    /// op.
+    ///
+    /// See [`PartialRead::position`](struct.PartialRead.html#method.position)
+    /// for more.
+    pub fn position(&mut self) -> io::Result<u64> {
+        self.inner.stream_position()
+    }
 }
 
 impl<W> Write for PartialWrite<W>
@@ -87,27 +418,125 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.ops.next() {
-            Some(PartialOp::Limited(n)) => {
-                let len = cmp::min(n, buf.len());
-                self.inner.write(&buf[..len])
+        self.ops.check_fail_flag(Operation::Write)?;
+        self.ops.check_poisoned(Operation::Write)?;
+        let mut buf = buf;
+        if let Some((watermark, kind)) = self.watermark {
+            let room = watermark.saturating_sub(self.bytes_transferred);
+            if room == 0 || (self.atomic_writes && room < buf.len() as u64) {
+                return Err(io::Error::new(
+                    kind,
+                    "write watermark reached, generated by partial-io",
+                ));
+            }
+            if room < buf.len() as u64 {
+                buf = &buf[..room as usize];
+            }
+        }
+
+        if let Some(sink) = &mut self.sink {
+            sink.remaining = sink.remaining.saturating_add((sink.refill)());
+            if sink.remaining == 0 && !buf.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "sink full, generated by partial-io",
+                ));
+            }
+            if sink.remaining < buf.len() {
+                buf = &buf[..sink.remaining];
             }
-            Some(PartialOp::Err(err)) => Err(io::Error::new(
-                err,
-                "error during write, generated by partial-io",
-            )),
-            Some(PartialOp::Unlimited) | None => self.inner.write(buf),
         }
+
+        if self.ops.fits_buffer_limit(buf.len()) {
+            let res = self.inner.write(buf);
+            self.account_written(&res);
+            return res;
+        }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n)) | Some(PartialOp::LimitedThenErr(n, _)) => {
+                self.write_limited(buf, n)
+            }
+            Some(PartialOp::PerSlice(ref caps)) => {
+                let n = first_cap(caps, buf.len());
+                self.write_limited(buf, n)
+            }
+            Some(PartialOp::DropTail(n)) => write_drop_tail(&mut self.inner, buf, n),
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Write)),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::BufferTooLarge(max)) => Err(buffer_too_large(max)),
+            Some(PartialOp::Delimiter(delimiter)) => {
+                let n = delimited_len(buf, delimiter);
+                self.write_limited(buf, n)
+            }
+            Some(PartialOp::Unlimited) | Some(PartialOp::FlushDrops) | None => {
+                self.inner.write(buf)
+            }
+        };
+        self.account_written(&res);
+        self.ops.complete(op, res.as_ref().ok().copied());
+        res
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        match self.ops.next() {
-            Some(PartialOp::Err(err)) => Err(io::Error::new(
-                err,
-                "error during flush, generated by partial-io",
-            )),
-            _ => self.inner.flush(),
+        self.ops.check_fail_flag(Operation::Flush)?;
+        self.ops.check_poisoned(Operation::Flush)?;
+        // A flush doesn't pass on a buffer, so a `BufferTooLarge` op stays in
+        // place for the next write.
+        if self.ops.fits_buffer_limit(0) {
+            return self.inner.flush();
         }
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Err(err)) => Err(injected_error(err, Operation::Flush)),
+            // There's no buffer of our own to drop, so this only skips the
+            // inner flush.
+            Some(PartialOp::FlushDrops) => Ok(()),
+            _ => self.inner.flush(),
+        };
+        self.ops.complete(op, res.as_ref().ok().map(|_| 0));
+        res
+    }
+}
+
+/// Writers whose accumulated contents can be viewed as a byte slice.
+///
+/// This is used by `written` on the write wrappers, and is implemented for the
+/// sinks commonly used in tests: `Vec<u8>` and `Cursor`s over anything that
+/// implements `AsRef<[u8]>`. (`Cursor` itself doesn't implement `AsRef<[u8]>`,
+/// which is why this trait exists.)
+pub trait WrittenBytes {
+    /// Returns the bytes held by this writer.
+    ///
+    /// For a `Cursor`, this is the full underlying buffer, not just the part
+    /// before the current position.
+    fn written_bytes(&self) -> &[u8];
+}
+
+impl WrittenBytes for Vec<u8> {
+    #[inline]
+    fn written_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<T> WrittenBytes for Cursor<T>
+where
+    T: AsRef<[u8]>,
+{
+    #[inline]
+    fn written_bytes(&self) -> &[u8] {
+        self.get_ref().as_ref()
+    }
+}
+
+impl<W> WrittenBytes for &mut W
+where
+    W: WrittenBytes + ?Sized,
+{
+    #[inline]
+    fn written_bytes(&self) -> &[u8] {
+        (**self).written_bytes()
     }
 }
 
@@ -122,13 +551,11 @@ where
     }
 }
 
-impl<W> fmt::Debug for PartialWrite<W>
-where
-    W: fmt::Debug,
-{
+impl<W> fmt::Debug for PartialWrite<W> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PartialWrite")
-            .field("inner", &self.inner)
+            .field("inner", &format_args!("{}", any::type_name::<W>()))
+            .field("ops_consumed", &self.ops.consumed())
             .finish()
     }
 }
@@ -138,6 +565,8 @@ mod tests {
     use super::*;
 
     use std::fs::File;
+    use std::io::BufWriter;
+    use std::iter;
 
     use crate::tests::assert_send;
 
@@ -145,4 +574,157 @@ mod tests {
     fn test_sendable() {
         assert_send::<PartialWrite<File>>();
     }
+
+    #[test]
+    fn test_flush_drops_skips_flush() {
+        // The inner writer isn't flushed, so the data stays in its buffer
+        // until the next flush. Nothing is lost.
+        let ops = vec![PartialOp::Unlimited, PartialOp::FlushDrops];
+        let mut partial_write = PartialWrite::new(BufWriter::new(Vec::new()), ops);
+        assert_eq!(partial_write.write(b"xyz").unwrap(), 3);
+        partial_write.flush().unwrap();
+        assert!(partial_write.get_ref().get_ref().is_empty());
+        partial_write.flush().unwrap();
+        assert_eq!(partial_write.get_ref().get_ref(), b"xyz");
+        // Nothing failed, so the op isn't counted as an error.
+        let stats = partial_write.stats();
+        assert_eq!((stats.other_errors, stats.unlimited), (0, 3));
+    }
+
+    #[test]
+    fn test_sink_capacity() {
+        let mut partial_write = PartialWrite::with_sink_capacity(
+            Vec::new(),
+            vec![PartialOp::Limited(3), PartialOp::Unlimited],
+            5,
+            || 0,
+        );
+        assert_eq!(partial_write.write(b"hello world").unwrap(), 3);
+        // Capped at the room left rather than by the op.
+        assert_eq!(partial_write.write(b"lo world").unwrap(), 2);
+        for _ in 0..2 {
+            let err = partial_write.write(b" world").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        }
+        assert_eq!(partial_write.ops_consumed(), 2);
+        assert_eq!(partial_write.get_ref(), b"hello");
+    }
+
+    #[test]
+    fn test_write_watermark() {
+        let mut partial_write =
+            PartialWrite::new(Vec::new(), iter::repeat(PartialOp::Limited(150)));
+        partial_write.set_write_watermark(400, io::ErrorKind::Other);
+
+        let payload = vec![7; 1000];
+        let err = partial_write.write_all(&payload).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(partial_write.bytes_transferred(), 400);
+        assert_eq!(partial_write.written().len(), 400);
+
+        // Every further write fails until the watermark is cleared.
+        assert_eq!(
+            partial_write.write(&payload[400..]).unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+        partial_write.clear_write_watermark();
+        partial_write.write_all(&payload[400..]).unwrap();
+        assert_eq!(partial_write.written(), &payload[..]);
+    }
+
+    #[test]
+    fn test_limited_then_err() {
+        let ops = vec![
+            PartialOp::LimitedThenErr(4, io::ErrorKind::ConnectionReset),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+
+        let err = partial_write.write_all(b"hello, world").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(partial_write.written(), b"hell");
+        // The error didn't consume the Unlimited op.
+        assert_eq!(partial_write.write(b"o").unwrap(), 1);
+        assert_eq!(partial_write.written(), b"hello");
+    }
+
+    #[test]
+    fn test_atomic_writes() {
+        let ops = vec![PartialOp::Limited(3), PartialOp::Limited(5)];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_atomic_writes(true);
+
+        let err = partial_write.write(b"hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(partial_write.written().is_empty());
+        assert_eq!(partial_write.write(b"hello").unwrap(), 5);
+        assert_eq!(partial_write.written(), b"hello");
+    }
+
+    #[test]
+    fn test_drop_tail() {
+        let ops = vec![PartialOp::DropTail(3), PartialOp::Unlimited];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+
+        assert_eq!(partial_write.write(b"hello").unwrap(), 5);
+        assert_eq!(partial_write.get_ref(), b"hel");
+        assert_eq!(partial_write.write(b"!").unwrap(), 1);
+        assert_eq!(partial_write.get_ref(), b"hel!");
+    }
+
+    #[test]
+    fn test_buffer_too_large() {
+        let ops = vec![PartialOp::BufferTooLarge(3), PartialOp::Limited(2)];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+
+        assert_eq!(partial_write.write(b"abc").unwrap(), 3);
+        assert_eq!(partial_write.ops_consumed(), 0);
+        let err = partial_write.write(b"defg").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(partial_write.ops_consumed(), 1);
+        assert_eq!(partial_write.write(b"defg").unwrap(), 2);
+        assert_eq!(partial_write.get_ref(), b"abcde");
+    }
+
+    #[test]
+    fn test_flush_keeps_buffer_too_large() {
+        let ops = vec![PartialOp::BufferTooLarge(4), PartialOp::Limited(1)];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+
+        partial_write.flush().unwrap();
+        assert_eq!(partial_write.ops_consumed(), 0);
+        let err = partial_write.write(&[0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(partial_write.write(&[0; 8]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_borrowed_inner() {
+        let mut out = Vec::new();
+        let mut partial_write = PartialWrite::new(&mut out, vec![PartialOp::Limited(2)]);
+        assert_eq!(partial_write.write(b"hello").unwrap(), 2);
+        drop(partial_write);
+        assert_eq!(out, b"he");
+
+        let stdout = io::stdout();
+        let mut partial_stdout = PartialWrite::new(stdout.lock(), vec![PartialOp::Limited(1)]);
+        partial_stdout.flush().unwrap();
+    }
+
+    #[test]
+    fn test_written_vec() {
+        let mut partial_write = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(2)]);
+        partial_write.write_all(b"hello").unwrap();
+        assert_eq!(partial_write.written(), b"hello");
+    }
+
+    #[test]
+    fn test_written_cursor() {
+        let mut partial_write =
+            PartialWrite::new(Cursor::new(Vec::new()), vec![PartialOp::Limited(2)]);
+        partial_write.write_all(b"hello").unwrap();
+        // The full buffer is returned regardless of the cursor position.
+        partial_write.get_mut().set_position(1);
+        assert_eq!(partial_write.written(), b"hello");
+    }
 }